@@ -0,0 +1,197 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::vm::MAX_REGISTERS;
+
+/// Non-fatal diagnostics produced while assembling a program. In strict mode
+/// these are promoted to errors.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssemblerWarning {
+    /// A jump resolves to a label declared in the data section.
+    JumpIntoData { instruction: u32, label: String },
+
+    /// A jump resolves to an offset beyond the end of the code section.
+    JumpOutOfRange {
+        instruction: u32,
+        label: String,
+        offset: u32,
+    },
+
+    /// The last instruction of the program neither halts nor jumps away, so
+    /// the VM runs off the end of the program.
+    MissingHalt { instruction: u32 },
+
+    /// A routine returns with values that it pushed still on the stack.
+    /// Holds the number of leftover values.
+    StackLeak { instruction: u32, depth: i32 },
+
+    /// A routine pops a value that it didn't push.
+    StackUnderflow { instruction: u32 },
+}
+
+impl fmt::Display for AssemblerWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblerWarning::JumpIntoData { instruction, label } => write!(
+                f,
+                "Instruction #{}: jump target @{} is in the data section",
+                instruction, label
+            ),
+            AssemblerWarning::JumpOutOfRange {
+                instruction,
+                label,
+                offset,
+            } => write!(
+                f,
+                "Instruction #{}: jump target @{} (offset {}) is beyond the end of the code section",
+                instruction, label, offset
+            ),
+            AssemblerWarning::MissingHalt { instruction } => write!(
+                f,
+                "Instruction #{}: program doesn't end with HLT",
+                instruction
+            ),
+            AssemblerWarning::StackLeak { instruction, depth } => write!(
+                f,
+                "Instruction #{}: routine returns with {} value(s) left on the stack",
+                instruction, depth
+            ),
+            AssemblerWarning::StackUnderflow { instruction } => write!(
+                f,
+                "Instruction #{}: routine pops more values than it pushed",
+                instruction
+            ),
+        }
+    }
+}
+
+/// Errors that make the assembler reject a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssemblerError {
+    /// The program couldn't be parsed. Holds the line number and the source
+    /// text of the line that failed.
+    ParseError { line: u32, fragment: String },
+
+    /// A label is used but never declared.
+    UndefinedLabel { instruction: u32, label: String },
+
+    /// A register beyond the ones supported by the VM.
+    InvalidRegister { instruction: u32, register: u8 },
+
+    /// An immediate operand that doesn't fit the 16 bits of the instruction.
+    /// Values from -32768 to 65535 are accepted.
+    ImmediateOutOfRange { instruction: u32, value: i32 },
+
+    /// A data directive declares a value that doesn't fit its width.
+    DataOutOfRange {
+        instruction: u32,
+        directive: String,
+        value: i32,
+    },
+
+    /// An `.align` directive whose boundary isn't a power of two.
+    InvalidAlignment { instruction: u32, alignment: i32 },
+
+    /// The code section is larger than the size requested by `.size`.
+    CodeTooLarge {
+        instruction: u32,
+        size: usize,
+        limit: i32,
+    },
+
+    /// A relative jump can't reach its label in the direction of the jump.
+    RelativeJumpOutOfRange { instruction: u32, label: String },
+
+    /// A call or conditional jump to a label in position independent mode.
+    /// Those only have absolute forms.
+    AbsoluteJump { instruction: u32, label: String },
+
+    /// A label's address doesn't fit in the 16-bit immediate that holds it.
+    LabelOutOfRange { instruction: u32, label: String },
+
+    /// A label is used where the instruction expects a register, e.g. the
+    /// target of `jpos $0 @label`.
+    LabelAsRegister { instruction: u32, label: String },
+
+    /// A warning that has been promoted to an error in strict mode.
+    Strict(AssemblerWarning),
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblerError::ParseError { line, fragment } => {
+                write!(f, "Line #{}: failed to parse `{}`", line, fragment)
+            }
+            AssemblerError::UndefinedLabel { instruction, label } => write!(
+                f,
+                "Instruction #{}: label @{} is not declared",
+                instruction, label
+            ),
+            AssemblerError::InvalidRegister {
+                instruction,
+                register,
+            } => write!(
+                f,
+                "Instruction #{}: register ${} is out of range (max ${})",
+                instruction,
+                register,
+                MAX_REGISTERS - 1
+            ),
+            AssemblerError::ImmediateOutOfRange { instruction, value } => write!(
+                f,
+                "Instruction #{}: immediate {} doesn't fit in 16 bits",
+                instruction, value
+            ),
+            AssemblerError::DataOutOfRange {
+                instruction,
+                directive,
+                value,
+            } => write!(
+                f,
+                "Instruction #{}: value {} doesn't fit in .{}",
+                instruction, value, directive
+            ),
+            AssemblerError::InvalidAlignment {
+                instruction,
+                alignment,
+            } => write!(
+                f,
+                "Instruction #{}: alignment {} is not a power of two",
+                instruction, alignment
+            ),
+            AssemblerError::CodeTooLarge {
+                instruction,
+                size,
+                limit,
+            } => write!(
+                f,
+                "Instruction #{}: code section takes {} bytes which doesn't fit in .size #{}",
+                instruction, size, limit
+            ),
+            AssemblerError::RelativeJumpOutOfRange { instruction, label } => write!(
+                f,
+                "Instruction #{}: relative jump can't reach @{}",
+                instruction, label
+            ),
+            AssemblerError::AbsoluteJump { instruction, label } => write!(
+                f,
+                "Instruction #{}: jump to @{} isn't position independent",
+                instruction, label
+            ),
+            AssemblerError::LabelOutOfRange { instruction, label } => write!(
+                f,
+                "Instruction #{}: @{} is too far away for a 16-bit address",
+                instruction, label
+            ),
+            AssemblerError::LabelAsRegister { instruction, label } => write!(
+                f,
+                "Instruction #{}: @{} can't be used in place of a register",
+                instruction, label
+            ),
+            AssemblerError::Strict(warning) => write!(f, "{}", warning),
+        }
+    }
+}
+
+impl Error for AssemblerError {}
@@ -8,7 +8,7 @@ use crate::opcode::Opcode;
 // intentially using 0xFF instead of 0 as '0' could be a valid
 // value for a register # i.e. div $1 $2 will end up encoded as
 // div $1 $2 $0.
-const PADDING: u8 = 0xFF;
+pub const PADDING: u8 = 0xFF;
 
 pub const INSTRUCTION_SIZE: u32 = 4;
 
@@ -24,36 +24,96 @@ pub struct AssemblyInstruction {
 }
 
 impl AssemblyInstruction {
-  pub fn to_bytes(&self, _st: &SymbolTable) -> Vec<u8> {
+  pub fn to_bytes(&self, st: &SymbolTable) -> Vec<u8> {
     let mut result = Vec::new();
-    match &self.opcode {
-      Some(op) => result.extend(op.to_bytes()),
-      _ => {
+    match self.encoded_opcode() {
+      Some(opcode) => result.push(opcode as u8),
+      None => {
         // For now, only the directives (.code, .asciiz, .data etc.) are the only
         // opcode less instructions that we support.
-        assert_eq!(
-          true,
-          self.has_directive(),
-          "Invalid instruction: No opcode found."
-        );
+        assert!(self.has_directive(), "Invalid instruction: No opcode found.");
       }
     };
 
+    // LOADW carries a full 32-bit immediate.
+    let is_wide = self.encoded_opcode() == Some(Opcode::LOADW);
+
     for operand in &[&self.operand1, &self.operand2, &self.operand3] {
       match operand {
+        Some(Token::IntegerOperand(v)) if is_wide => result.extend(&v.to_be_bytes()),
+        Some(Token::LabelUsage(label)) if is_wide => {
+          let offset = st.get(label).map_or(0, |info| info.offset());
+          result.extend(&(offset as i32).to_be_bytes());
+        }
+        // Labels are resolved to their 16-bit offsets. Undeclared labels are
+        // reported by the assembler before we get here, so just emit zero.
+        Some(Token::LabelUsage(label)) => {
+          let offset = st.get(label).map_or(0, |info| info.offset());
+          result.extend(&(offset as u16).to_be_bytes());
+        }
         Some(t) => result.extend(t.to_bytes()),
         None => (),
       }
     }
 
-    // Pad the instructions to make them 4-bytes.
-    while result.len() < INSTRUCTION_SIZE as usize {
+    // Pad the instructions to their full size.
+    let size = self
+      .encoded_opcode()
+      .map_or(INSTRUCTION_SIZE as usize, |op| op.instruction_size());
+    while result.len() < size {
       result.push(PADDING);
     }
 
     result
   }
 
+  /// Number of bytes this instruction takes in the code section. Directives
+  /// don't take up any space.
+  pub fn size(&self) -> u32 {
+    match self.encoded_opcode() {
+      Some(opcode) => opcode.instruction_size() as u32,
+      None => 0,
+    }
+  }
+
+  /// Number of bytes this instruction takes in the data section.
+  pub fn data_size(&self) -> u32 {
+    match self.get_directive().as_deref() {
+      Some("word") => 2,
+      Some("long") => 4,
+      Some("asciiz") => self.asciiz_bytes().map_or(0, |bytes| bytes.len() as u32),
+      _ => 0,
+    }
+  }
+
+  /// Bytes of the string declared by an `.asciiz` directive, including the
+  /// terminating null byte, with the escape sequences resolved.
+  pub fn asciiz_bytes(&self) -> Option<Vec<u8>> {
+    if self.get_directive().as_deref() != Some("asciiz") {
+      return None;
+    }
+    match &self.operand1 {
+      Some(Token::StringOperand(s)) => {
+        let mut bytes = unescape(s).into_bytes();
+        bytes.push(0);
+        Some(bytes)
+      }
+      _ => None,
+    }
+  }
+
+  /// Boundary requested by an `.align #N` directive. Returns None for other
+  /// instructions or if N isn't a power of two.
+  pub fn alignment(&self) -> Option<u32> {
+    if self.get_directive().as_deref() != Some("align") {
+      return None;
+    }
+    match self.operand1 {
+      Some(Token::IntegerOperand(n)) if n > 0 && (n as u32).is_power_of_two() => Some(n as u32),
+      _ => None,
+    }
+  }
+
   pub fn has_label(&self) -> bool {
     self.label.is_some()
   }
@@ -65,6 +125,37 @@ impl AssemblyInstruction {
     }
   }
 
+  /// Returns the name of the first label used as an operand, if any.
+  pub fn get_label_usage(&self) -> Option<String> {
+    for operand in &[&self.operand1, &self.operand2, &self.operand3] {
+      if let Some(Token::LabelUsage(label)) = operand {
+        return Some(label.clone());
+      }
+    }
+    None
+  }
+
+  /// Opcode the instruction is encoded with, which can differ from the one
+  /// written in the source:
+  /// - The target of a jump or call to a label is known up front so it's
+  ///   encoded as an immediate instead of going through a register.
+  /// - LOAD only carries a 16-bit value that is zero-extended, so values that
+  ///   don't fit (including negative ones) are loaded with the 8 byte LOADW.
+  pub fn encoded_opcode(&self) -> Option<Opcode> {
+    let opcode = self.get_opcode()?;
+    let encoded = match (opcode, &self.operand1, &self.operand2) {
+      (Opcode::JMP, Some(Token::LabelUsage(_)), _) => Opcode::JMPI,
+      (Opcode::CALL, Some(Token::LabelUsage(_)), _) => Opcode::CALLI,
+      (Opcode::JEQ, Some(Token::LabelUsage(_)), _) => Opcode::JEQI,
+      (Opcode::JNEQ, Some(Token::LabelUsage(_)), _) => Opcode::JNEQI,
+      (Opcode::JO, Some(Token::LabelUsage(_)), _) => Opcode::JOI,
+      (Opcode::JNO, Some(Token::LabelUsage(_)), _) => Opcode::JNOI,
+      (Opcode::LOAD, _, Some(Token::IntegerOperand(v))) if !(0..=0xFFFF).contains(v) => Opcode::LOADW,
+      _ => opcode,
+    };
+    Some(encoded)
+  }
+
   pub fn has_opcode(&self) -> bool {
     self.opcode.is_some()
   }
@@ -88,6 +179,26 @@ impl AssemblyInstruction {
   }
 }
 
+// Resolves the escape sequences accepted by the string parser.
+fn unescape(s: &str) -> String {
+  let mut result = String::with_capacity(s.len());
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      result.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('n') => result.push('\n'),
+      Some('t') => result.push('\t'),
+      Some('r') => result.push('\r'),
+      Some(c) => result.push(c),
+      None => result.push('\\'),
+    }
+  }
+  result
+}
+
 impl fmt::Display for AssemblyInstruction {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     write!(
@@ -101,6 +212,7 @@ impl fmt::Display for AssemblyInstruction {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::assembler::symbols::{SymbolInfo, SymbolType};
   #[test]
   fn test_assembly_instruction_to_bytes() {
     let st = SymbolTable::new();
@@ -121,6 +233,93 @@ mod tests {
     assert_eq!(eq.to_bytes(&st), vec![Opcode::EQ as u8, 10, 20, PADDING]);
   }
 
+  #[test]
+  fn test_wide_instruction_to_bytes() {
+    let st = SymbolTable::new();
+    let loadw = AssemblyInstruction {
+      opcode: Some(Token::Opcode(Opcode::LOADW)),
+      operand1: Some(Token::Register(3)),
+      operand2: Some(Token::IntegerOperand(100_000)),
+      ..Default::default()
+    };
+    assert_eq!(loadw.size(), 8);
+    assert_eq!(
+      loadw.to_bytes(&st),
+      vec![Opcode::LOADW as u8, 3, 0, 1, 0x86, 0xA0, PADDING, PADDING]
+    );
+  }
+
+  #[test]
+  fn test_load_widened_to_bytes() {
+    let st = SymbolTable::new();
+    let load = |value| AssemblyInstruction {
+      opcode: Some(Token::Opcode(Opcode::LOAD)),
+      operand1: Some(Token::Register(1)),
+      operand2: Some(Token::IntegerOperand(value)),
+      ..Default::default()
+    };
+    assert_eq!(load(65535).encoded_opcode(), Some(Opcode::LOAD));
+    assert_eq!(load(65535).size(), 4);
+
+    assert_eq!(load(100_000).encoded_opcode(), Some(Opcode::LOADW));
+    assert_eq!(load(100_000).size(), 8);
+    assert_eq!(
+      load(-1).to_bytes(&st),
+      vec![Opcode::LOADW as u8, 1, 0xFF, 0xFF, 0xFF, 0xFF, PADDING, PADDING]
+    );
+  }
+
+  #[test]
+  fn test_jump_to_label_to_bytes() {
+    let mut st = SymbolTable::new();
+    st.insert("loop".to_string(), SymbolInfo::new(0x0048, SymbolType::Label));
+    let jmp = AssemblyInstruction {
+      opcode: Some(Token::Opcode(Opcode::JMP)),
+      operand1: Some(Token::LabelUsage("loop".to_string())),
+      ..Default::default()
+    };
+    assert_eq!(jmp.to_bytes(&st), vec![Opcode::JMPI as u8, 0, 0x48, PADDING]);
+
+    let jmp = AssemblyInstruction {
+      opcode: Some(Token::Opcode(Opcode::JMP)),
+      operand1: Some(Token::Register(2)),
+      ..Default::default()
+    };
+    assert_eq!(jmp.to_bytes(&st), vec![Opcode::JMP as u8, 2, PADDING, PADDING]);
+  }
+
+  #[test]
+  fn test_label_usage_to_bytes() {
+    let mut st = SymbolTable::new();
+    st.insert("target".to_string(), SymbolInfo::new(0x0104, SymbolType::Label));
+    let load = AssemblyInstruction {
+      opcode: Some(Token::Opcode(Opcode::LOAD)),
+      operand1: Some(Token::Register(1)),
+      operand2: Some(Token::LabelUsage("target".to_string())),
+      ..Default::default()
+    };
+    assert_eq!(load.to_bytes(&st), vec![Opcode::LOAD as u8, 1, 0x01, 0x04]);
+    assert_eq!(load.get_label_usage(), Some("target".to_string()));
+  }
+
+  #[test]
+  fn test_asciiz_bytes() {
+    let inst = AssemblyInstruction {
+      directive: Some(Token::Directive("asciiz".to_string())),
+      operand1: Some(Token::StringOperand(r#"Hi\t\"you\"\n"#.to_string())),
+      ..Default::default()
+    };
+    assert_eq!(inst.asciiz_bytes(), Some(b"Hi\t\"you\"\n\0".to_vec()));
+    assert_eq!(inst.data_size(), 10);
+
+    let word = AssemblyInstruction {
+      directive: Some(Token::Directive("word".to_string())),
+      operand1: Some(Token::IntegerOperand(1)),
+      ..Default::default()
+    };
+    assert_eq!(word.asciiz_bytes(), None);
+  }
+
   #[test]
   fn test_opcode_less_instruction() {
     let st = SymbolTable::new();
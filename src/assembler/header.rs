@@ -0,0 +1,172 @@
+use std::error::Error;
+use std::fmt;
+
+use super::{
+    BIN_CODE_START_OFFSET, BIN_DATA_START_OFFSET, BIN_HEADER_LENGTH, BIN_HEADER_PREFIX,
+    BIN_VERSION, BIN_VERSION_OFFSET,
+};
+
+/// Errors encountered while parsing an executable header.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderError {
+    /// Buffer is shorter than the header. Holds the length of the buffer.
+    TooShort(usize),
+
+    /// Buffer doesn't start with the magic prefix.
+    InvalidMagic,
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderError::TooShort(len) => write!(
+                f,
+                "header needs {} bytes but only {} are available",
+                BIN_HEADER_LENGTH, len
+            ),
+            HeaderError::InvalidMagic => write!(f, "invalid magic prefix"),
+        }
+    }
+}
+
+impl Error for HeaderError {}
+
+/// Structured representation of the executable header. See the layout
+/// described next to BIN_HEADER_LENGTH.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinaryHeader {
+    pub magic: [u8; 4],
+    pub version: u8,
+
+    /// Offset of the data section in the executable or 0 if there is none.
+    pub data_start: u32,
+
+    /// Offset of the code section in the executable. 0 means that it starts
+    /// right after the header.
+    pub code_start: u32,
+}
+
+impl BinaryHeader {
+    /// Header for executables produced by this version of the assembler.
+    pub fn new() -> Self {
+        BinaryHeader {
+            magic: BIN_HEADER_PREFIX,
+            version: BIN_VERSION,
+            data_start: 0,
+            code_start: BIN_HEADER_LENGTH as u32,
+        }
+    }
+
+    /// Offset at which execution starts.
+    pub fn entry_point(&self) -> usize {
+        match self.code_start {
+            0 => BIN_HEADER_LENGTH,
+            start => start as usize,
+        }
+    }
+
+    /// Parses the header at the start of the specified bytes.
+    pub fn parse(bytes: &[u8]) -> Result<Self, HeaderError> {
+        if bytes.len() < BIN_HEADER_LENGTH {
+            return Err(HeaderError::TooShort(bytes.len()));
+        }
+
+        if !bytes.starts_with(&BIN_HEADER_PREFIX) {
+            return Err(HeaderError::InvalidMagic);
+        }
+
+        let read_u32 = |offset: usize| {
+            let mut value = [0; 4];
+            value.copy_from_slice(&bytes[offset..offset + 4]);
+            u32::from_be_bytes(value)
+        };
+
+        Ok(BinaryHeader {
+            magic: BIN_HEADER_PREFIX,
+            version: bytes[BIN_VERSION_OFFSET],
+            data_start: read_u32(BIN_DATA_START_OFFSET),
+            code_start: read_u32(BIN_CODE_START_OFFSET),
+        })
+    }
+
+    /// Serializes the header. Unused bytes are padded with zeros.
+    pub fn to_bytes(&self) -> [u8; BIN_HEADER_LENGTH] {
+        let mut header = [0; BIN_HEADER_LENGTH];
+        header[..self.magic.len()].copy_from_slice(&self.magic);
+        header[BIN_VERSION_OFFSET] = self.version;
+        header[BIN_DATA_START_OFFSET..BIN_DATA_START_OFFSET + 4]
+            .copy_from_slice(&self.data_start.to_be_bytes());
+        header[BIN_CODE_START_OFFSET..BIN_CODE_START_OFFSET + 4]
+            .copy_from_slice(&self.code_start.to_be_bytes());
+        header
+    }
+}
+
+impl Default for BinaryHeader {
+    fn default() -> Self {
+        BinaryHeader::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        let mut bytes = BinaryHeader::new().to_bytes().to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+
+        let header = BinaryHeader::parse(&bytes).unwrap();
+        assert_eq!(header.magic, BIN_HEADER_PREFIX);
+        assert_eq!(header.version, BIN_VERSION);
+        assert_eq!(header.data_start, 0);
+        assert_eq!(header.code_start, BIN_HEADER_LENGTH as u32);
+        assert_eq!(header.entry_point(), BIN_HEADER_LENGTH);
+    }
+
+    #[test]
+    fn test_missing_code_start() {
+        let mut bytes = [0; BIN_HEADER_LENGTH];
+        bytes[..4].copy_from_slice(&BIN_HEADER_PREFIX);
+
+        let header = BinaryHeader::parse(&bytes).unwrap();
+        assert_eq!(header.code_start, 0);
+        assert_eq!(header.entry_point(), BIN_HEADER_LENGTH);
+    }
+
+    #[test]
+    fn test_parse_short_header() {
+        assert_eq!(
+            BinaryHeader::parse(&BIN_HEADER_PREFIX),
+            Err(HeaderError::TooShort(4))
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_magic() {
+        assert_eq!(
+            BinaryHeader::parse(&[0; BIN_HEADER_LENGTH]),
+            Err(HeaderError::InvalidMagic)
+        );
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let mut header = BinaryHeader::new();
+        header.version = 7;
+        header.data_start = 0x0102_0304;
+        header.code_start = 0x80;
+
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[..4], &BIN_HEADER_PREFIX);
+        assert_eq!(bytes[BIN_VERSION_OFFSET], 7);
+        assert_eq!(
+            &bytes[BIN_DATA_START_OFFSET..BIN_DATA_START_OFFSET + 4],
+            &[1, 2, 3, 4]
+        );
+        assert_eq!(bytes[BIN_CODE_START_OFFSET + 3], 0x80);
+        assert_eq!(header.entry_point(), 0x80);
+        assert_eq!(BinaryHeader::parse(&bytes), Ok(header));
+    }
+}
@@ -1,14 +1,27 @@
 /// This module contains implementation of our simple two-pass assembler
 /// for the Iridium VM.
+pub mod assembler_errors;
 pub mod assembly_instruction;
+pub mod header;
 pub mod parsers;
 pub mod program;
 pub mod symbols;
 pub mod token;
 
-use crate::vm::VM;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::disassembler::{operand_kinds, OperandKind};
+use crate::opcode::Opcode;
+use crate::vm::{MAX_REGISTERS, VM};
+use assembler_errors::{AssemblerError, AssemblerWarning};
+use assembly_instruction::{AssemblyInstruction, INSTRUCTION_SIZE, PADDING};
+use header::BinaryHeader;
+use parsers::AssemblerSyntax;
 use program::Program;
 use symbols::{SymbolInfo, SymbolTable, SymbolType};
+use token::Token;
 
 /// Executable header has the following format:
 ///      |---------------------------------------------------------|
@@ -17,7 +30,15 @@ use symbols::{SymbolInfo, SymbolTable, SymbolType};
 ///      |---------------------------------------------------------|
 ///      | Bytes[4] Contains 1 byte version. Its set to 1 for now. |
 ///      |---------------------------------------------------------|
-///      | Remaining 59 bytes are padded with zeros for now.       |
+///      | Bytes[8..12] contain the big-endian offset of the data  |
+///      |       section in the executable or 0 if there is none.  |
+///      |       Data segments are concatenated in the order they  |
+///      |       are declared and loaded at the start of the heap. |
+///      |---------------------------------------------------------|
+///      | Bytes[12..16] contain the big-endian offset of the code |
+///      |       section. 0 means it starts right after the header.|
+///      |---------------------------------------------------------|
+///      | Remaining bytes are padded with zeros for now.          |
 ///      |---------------------------------------------------------|
 
 pub const BIN_HEADER_LENGTH: usize = 64;
@@ -28,6 +49,10 @@ pub const BIN_HEADER_PREFIX: [u8; 4] = [0x41, 0x5A, 0x41, 0x44];
 pub const BIN_VERSION_OFFSET: usize = 4; // fifth byte.
 pub const BIN_VERSION: u8 = 1;
 
+pub const BIN_DATA_START_OFFSET: usize = 8;
+
+pub const BIN_CODE_START_OFFSET: usize = 12;
+
 #[derive(Debug, Clone)]
 pub enum AssemblerPass {
     // In the first pass, we just collect all the symbols/labels and their
@@ -39,13 +64,27 @@ pub enum AssemblerPass {
     Second,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Section {
     start: Option<usize>,
     size: Option<usize>,
 }
 
-#[derive(Debug)]
+impl Section {
+    /// Start of the section. Code sections start at an address in the
+    /// executable while data sections start at an offset into the data.
+    pub fn start(&self) -> Option<usize> {
+        self.start
+    }
+
+    /// Number of bytes in the section. It's known once the first pass is
+    /// done with the section.
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum AssemblerSection {
     /// Code section. Start signifies the start of section
     Code(Section),
@@ -97,6 +136,28 @@ pub struct Assembler {
     /// roughly the line # of the input program and we use it to report
     /// diagnostic messages.
     current_instruction: u32,
+
+    /// Errors encountered while assembling the last program.
+    errors: Vec<AssemblerError>,
+
+    /// Warnings encountered while assembling the last program.
+    warnings: Vec<AssemblerWarning>,
+
+    /// In strict mode warnings are treated as errors.
+    strict: bool,
+
+    /// Dialect of the programs that we assemble.
+    syntax: AssemblerSyntax,
+
+    /// Emit pc-relative jumps to labels so the code can be relocated.
+    position_independent: bool,
+
+    /// Check that routines leave the stack as they found it.
+    check_stack: bool,
+
+    /// Executable header. It never changes so we build it once and hand out
+    /// copies for every assembled program.
+    header: Vec<u8>,
 }
 
 impl Assembler {
@@ -110,78 +171,673 @@ impl Assembler {
             segments: vec![],
             current_section: AssemblerSection::Unknown,
             current_instruction: 0,
+            errors: vec![],
+            warnings: vec![],
+            strict: false,
+            syntax: AssemblerSyntax::default(),
+            position_independent: false,
+            check_stack: false,
+            header: Assembler::generate_header(),
         }
     }
 
+    /// Returns the cached executable header.
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    /// Treat warnings as errors.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Emit `jmp @label`, `jmpf @label` and `jmpb @label` as jumps relative
+    /// to the jump instruction instead of absolute addresses. The register
+    /// forms can't hold an offset so they're encoded as JMPFI and JMPBI.
+    /// Calls and conditional jumps to labels are reported as errors.
+    pub fn set_position_independent(&mut self, position_independent: bool) {
+        self.position_independent = position_independent;
+    }
+
+    /// Warn about routines that don't pop everything they push or pop more
+    /// than they push. It's a heuristic so it's off by default.
+    pub fn set_check_stack(&mut self, check_stack: bool) {
+        self.check_stack = check_stack;
+    }
+
+    /// Assemble programs written in the specified dialect.
+    pub fn set_syntax(&mut self, syntax: AssemblerSyntax) {
+        self.syntax = syntax;
+    }
+
+    /// Writes the labels of the last assembled program to a sidecar symbol
+    /// map that the VM can load with VM::load_symbols.
+    pub fn write_symbol_map<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, symbols::format_symbol_map(&self.symbol_table))
+    }
+
+    /// Assembles the program and writes the executable, header included, to
+    /// the specified file. The VM can run it with VM::load_from_file.
+    /// Assembly errors are reported as InvalidInput.
+    pub fn assemble_to_file<P: AsRef<Path>>(&mut self, raw: &str, path: P) -> io::Result<()> {
+        let program = self
+            .assemble(raw)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        fs::write(path, program)
+    }
+
+    /// Sections declared by the last assembled program in the order they
+    /// appear in the source.
+    pub fn sections(&self) -> &[AssemblerSection] {
+        &self.segments
+    }
+
+    /// Errors reported while assembling the last program.
+    pub fn errors(&self) -> &[AssemblerError] {
+        &self.errors
+    }
+
+    /// Warnings reported while assembling the last program.
+    pub fn warnings(&self) -> &[AssemblerWarning] {
+        &self.warnings
+    }
+
     pub fn generate_header() -> Vec<u8> {
-        let mut header = vec![0; BIN_HEADER_LENGTH];
+        BinaryHeader::new().to_bytes().to_vec()
+    }
 
-        // Write magic number.
-        for (i, v) in BIN_HEADER_PREFIX.into_iter().enumerate() {
-            header[i] = *v;
+    /// Assembles the specified program. If it fails, the first error is
+    /// returned and all of them are available through errors().
+    pub fn assemble(&mut self, prog: &str) -> Result<Vec<u8>, AssemblerError> {
+        match self.parse(prog) {
+            Ok((program, _source)) => self.assemble_program(&program),
+            Err(e) => {
+                self.reset();
+                self.errors.push(e.clone());
+                Err(e)
+            }
         }
-        header[BIN_VERSION_OFFSET] = BIN_VERSION;
-        header
     }
 
-    /// Assembles the specified program.
-    pub fn assemble(&mut self, prog: &str) -> Option<Vec<u8>> {
-        match parsers::parse_program(prog) {
-            // TODO: Deal with _leftover. This should be an error if the
-            // parser can't fully consume the program.
-            Ok((_leftover, program)) => {
-                // Generate header.
-                let mut executable = Assembler::generate_header();
+    // Parses the whole program. Unlike the parsers, input that can't be
+    // consumed is an error as well.
+    fn parse<'a>(&self, prog: &'a str) -> Result<(Program, Vec<&'a str>), AssemblerError> {
+        let rest = match parsers::parse_program_with_source(&self.syntax, prog) {
+            Ok(("", result)) => return Ok(result),
+            Ok((leftover, _)) => leftover,
+            Err(nom::Err::Error((rest, _))) | Err(nom::Err::Failure((rest, _))) => rest,
+            Err(nom::Err::Incomplete(_)) => "",
+        };
 
-                // Generate bytecode.
-                self.run_pass1(&program);
-                let mut bytecode = self.run_pass2(&program);
+        // The parsers only hand out slices of the input so the failing line
+        // can be located from where what's left starts. Some of them trim
+        // the input, so it's kept on a character boundary to be safe.
+        let mut offset = (rest.as_ptr() as usize)
+            .saturating_sub(prog.as_ptr() as usize)
+            .min(prog.len());
+        while !prog.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let start = prog[..offset].rfind('\n').map_or(0, |n| n + 1);
+        Err(AssemblerError::ParseError {
+            line: prog[..offset].matches('\n').count() as u32 + 1,
+            fragment: prog[start..]
+                .lines()
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_string(),
+        })
+    }
 
-                // Append the bytecode to the executable.
-                executable.append(&mut bytecode);
-                Some(executable)
-            }
-            Err(e) => {
-                eprintln!("Failed to assemble program. Error: {:?}", e);
-                None
+    /// Assembles the specified program and also produces a listing that shows
+    /// the address, the emitted bytes and the source of every instruction.
+    pub fn assemble_with_listing(
+        &mut self,
+        prog: &str,
+    ) -> Result<(Vec<u8>, String), AssemblerError> {
+        let (program, source) = self.parse(prog)?;
+
+        let executable = self.assemble_program(&program)?;
+
+        let mut listing = String::new();
+        let mut pc = BIN_HEADER_LENGTH;
+        for (i, line) in program.instructions.iter().zip(source) {
+            let mut hex = vec![];
+            if i.has_opcode() {
+                for byte in i.to_bytes(&self.symbol_table) {
+                    hex.push(format!("{:02x}", byte));
+                }
             }
+            listing.push_str(&format!("{:04x}  {:<11}  {}\n", pc, hex.join(" "), line));
+            pc += hex.len();
         }
+
+        Ok((executable, listing))
+    }
+
+    /// Runs only the first pass over the specified program and returns the
+    /// resolved symbol table without generating any bytecode.
+    pub fn resolve_symbols(&mut self, prog: &str) -> Result<SymbolTable, AssemblerError> {
+        let (program, _source) = self.parse(prog)?;
+        self.run_pass1_only(&program);
+        Ok(std::mem::take(&mut self.symbol_table))
+    }
+
+    /// Runs the first pass over a parsed program and returns the symbol table
+    /// it built. Together with run_pass2_only, this lets a front-end show the
+    /// state of the assembler after each pass.
+    pub fn run_pass1_only(&mut self, program: &Program) -> &SymbolTable {
+        self.reset();
+        self.run_pass1(program);
+        &self.symbol_table
+    }
+
+    /// Runs the second pass over a parsed program and returns the executable.
+    /// The first pass is run as well if it hasn't been already.
+    pub fn run_pass2_only(&mut self, program: &Program) -> Result<Vec<u8>, AssemblerError> {
+        if let AssemblerPass::First = self.pass {
+            self.run_pass1(program);
+        }
+        let mut bytecode = self.run_pass2(program);
+
+        if let Some(e) = self.errors.first() {
+            return Err(e.clone());
+        }
+
+        // Generate header. It only differs from the cached one if there is a
+        // data section to locate.
+        let mut executable = if self.data.is_empty() {
+            self.header.clone()
+        } else {
+            let header = BinaryHeader {
+                data_start: (BIN_HEADER_LENGTH + bytecode.len()) as u32,
+                ..BinaryHeader::new()
+            };
+            header.to_bytes().to_vec()
+        };
+
+        // Append the bytecode followed by the data section to the executable.
+        executable.append(&mut bytecode);
+        executable.extend_from_slice(&self.data);
+        Ok(executable)
+    }
+
+    // Runs both the passes over a parsed program and generates the executable.
+    fn assemble_program(&mut self, program: &Program) -> Result<Vec<u8>, AssemblerError> {
+        self.run_pass1_only(program);
+        self.run_pass2_only(program)
+    }
+
+    // Clears the state left behind by a previous program.
+    fn reset(&mut self) {
+        self.pass = AssemblerPass::First;
+        self.symbol_table.clear();
+        self.segments.clear();
+        self.current_section = AssemblerSection::Unknown;
+        self.current_instruction = 0;
+        self.errors.clear();
+        self.warnings.clear();
+        self.data.clear();
+    }
+
+    fn in_data_section(&self) -> bool {
+        matches!(self.current_section, AssemblerSection::Data(_))
     }
 
     // Runs first pass of the assembler. Here we basically just build the
-    // symbol table for all the labels and record their offsets.
+    // symbol table for all the labels and record their offsets. Symbols in
+    // data sections are recorded too so code can refer to data declared
+    // after it.
     fn run_pass1(&mut self, prog: &Program) {
-        // program counter.
-        let mut pc = 0;
+        // program counter. Code follows the executable header so labels are
+        // recorded as absolute addresses that the VM can jump to.
+        let mut pc = BIN_HEADER_LENGTH as u32;
+
+        // Offset into the data section.
+        let mut data_offset = 0;
 
         // Record addresses of all labels in the symbol table.
         for i in &prog.instructions {
-            if i.has_label() {
-                match i.get_label() {
-                    Some(name) => {
-                        let info = SymbolInfo::new(pc, SymbolType::Label);
-                        self.symbol_table.insert(name, info);
+            if let Some(directive) = i.get_directive() {
+                match AssemblerSection::from(directive.as_str()) {
+                    AssemblerSection::Code(_) => {
+                        self.close_section(pc as usize, data_offset as usize);
+                        self.switch_section(AssemblerSection::Code(Section {
+                            start: Some(pc as usize),
+                            size: None,
+                        }));
+                    }
+                    AssemblerSection::Data(_) => {
+                        self.close_section(pc as usize, data_offset as usize);
+                        self.switch_section(AssemblerSection::Data(Section {
+                            start: Some(data_offset as usize),
+                            size: None,
+                        }));
                     }
-                    None => (),
+                    AssemblerSection::Unknown => (),
                 }
             }
 
-            pc += assembly_instruction::INSTRUCTION_SIZE;
+            // Pad the current section up to the requested boundary before
+            // recording the label so that it points at the aligned offset.
+            if let Some(alignment) = i.alignment() {
+                if self.in_data_section() {
+                    data_offset += padding(data_offset, alignment);
+                } else {
+                    pc += padding(pc, alignment);
+                }
+            }
+
+            if let Some(name) = i.get_label() {
+                let info = if self.in_data_section() {
+                    let symbol_type = match i.get_directive() {
+                        Some(ref d) if d == "asciiz" => SymbolType::String,
+                        _ => SymbolType::Integer,
+                    };
+                    SymbolInfo::new(data_offset, symbol_type)
+                } else {
+                    SymbolInfo::new(pc, SymbolType::Label)
+                };
+                self.symbol_table.insert(name, info);
+            }
+
+            // Directives don't take up any space in the code section while
+            // data declarations always go to the data section.
+            pc += i.size();
+            data_offset += i.data_size();
         }
 
+        self.close_section(pc as usize, data_offset as usize);
+
         // We are ready to move to next pass.
         self.pass = AssemblerPass::Second;
     }
 
+    // Records the size of the section that was declared last now that we know
+    // where it ends.
+    fn close_section(&mut self, pc: usize, data_offset: usize) {
+        match self.segments.last_mut() {
+            Some(AssemblerSection::Code(section)) => {
+                section.size = section.start.map(|start| pc - start);
+            }
+            Some(AssemblerSection::Data(section)) => {
+                section.size = section.start.map(|start| data_offset - start);
+            }
+            _ => (),
+        }
+    }
+
+    fn switch_section(&mut self, section: AssemblerSection) {
+        self.segments.push(section.clone());
+        self.current_section = section;
+    }
+
+    // Tracks the section during the second pass. Unlike switch_section, it
+    // doesn't record any segments.
+    fn update_section(&mut self, i: &AssemblyInstruction) {
+        if let Some(directive) = i.get_directive() {
+            match AssemblerSection::from(directive.as_str()) {
+                AssemblerSection::Unknown => (),
+                section => self.current_section = section,
+            }
+        }
+    }
+
     // Run second pass where we generate complete byte-code.
     fn run_pass2(&mut self, prog: &Program) -> Vec<u8> {
-        prog.to_bytes(&self.symbol_table)
+        let mut code = vec![];
+        self.current_section = AssemblerSection::Unknown;
+        for (n, i) in prog.instructions.iter().enumerate() {
+            self.update_section(i);
+            if let Some(alignment) = i.alignment() {
+                if !self.in_data_section() {
+                    // Instructions are 4 bytes and so is the gap. It's filled
+                    // with NOPs so that code can fall through it.
+                    let pc = (BIN_HEADER_LENGTH + code.len()) as u32;
+                    for _ in 0..padding(pc, alignment) / INSTRUCTION_SIZE {
+                        code.extend_from_slice(&[Opcode::NOP as u8, PADDING, PADDING, PADDING]);
+                    }
+                }
+            }
+            if i.has_opcode() {
+                let pc = (BIN_HEADER_LENGTH + code.len()) as u32;
+                match self.relative_jump(n as u32 + 1, i, pc) {
+                    Some(mut bytes) => code.append(&mut bytes),
+                    None => code.append(&mut i.to_bytes(&self.symbol_table)),
+                }
+            }
+        }
+
+        self.pad_code(prog, &mut code);
+        self.check_terminated(prog);
+        if self.check_stack {
+            self.check_stack_balance(prog);
+        }
+
+        self.current_section = AssemblerSection::Unknown;
+        for (n, i) in prog.instructions.iter().enumerate() {
+            self.current_instruction = n as u32 + 1;
+            self.update_section(i);
+            self.check_registers(i);
+            self.check_immediates(i);
+            self.check_label_operands(i);
+            self.emit_data(i);
+            if let Some(label) = i.get_label_usage() {
+                self.check_label_usage(i, label, BIN_HEADER_LENGTH + code.len());
+            }
+        }
+
+        code
     }
+
+    // Encodes `jmp @label`, `jmpf @label` or `jmpb @label` at the specified
+    // address as a pc-relative jump in position independent mode. Returns None
+    // if the instruction should be encoded as usual.
+    fn relative_jump(
+        &mut self,
+        instruction: u32,
+        i: &AssemblyInstruction,
+        pc: u32,
+    ) -> Option<Vec<u8>> {
+        if !self.position_independent {
+            return None;
+        }
+        let label = match &i.operand1 {
+            Some(Token::LabelUsage(label)) => label,
+            _ => return None,
+        };
+        let target = self.symbol_table.get(label)?.offset();
+
+        let (opcode, offset) = match i.get_opcode()? {
+            Opcode::JMPF => (Opcode::JMPFI, target.checked_sub(pc)),
+            Opcode::JMPB => (Opcode::JMPBI, pc.checked_sub(target)),
+            Opcode::JMP if target >= pc => (Opcode::JMPFI, Some(target - pc)),
+            Opcode::JMP => (Opcode::JMPBI, Some(pc - target)),
+            Opcode::CALL | Opcode::JEQ | Opcode::JNEQ | Opcode::JO | Opcode::JNO => {
+                self.errors.push(AssemblerError::AbsoluteJump {
+                    instruction,
+                    label: label.clone(),
+                });
+                return None;
+            }
+            _ => return None,
+        };
+        match offset.filter(|&offset| offset <= u32::from(u16::MAX)) {
+            Some(offset) => {
+                let mut bytes = vec![opcode as u8];
+                bytes.extend_from_slice(&(offset as u16).to_be_bytes());
+                bytes.push(PADDING);
+                Some(bytes)
+            }
+            None => {
+                self.errors.push(AssemblerError::RelativeJumpOutOfRange {
+                    instruction,
+                    label: label.clone(),
+                });
+                None
+            }
+        }
+    }
+
+    // Pads the code section with zeros i.e. HLT instructions up to the size
+    // requested by a `.size #N` directive. This is used to produce images for
+    // targets with a fixed capacity.
+    fn pad_code(&mut self, prog: &Program, code: &mut Vec<u8>) {
+        let (n, i) = match prog
+            .instructions
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, i)| i.get_directive().as_deref() == Some("size"))
+        {
+            Some(directive) => directive,
+            None => return,
+        };
+
+        let limit = match i.operand1 {
+            Some(Token::IntegerOperand(limit)) => limit,
+            _ => 0,
+        };
+        if limit < 0 || code.len() > limit as usize {
+            self.errors.push(AssemblerError::CodeTooLarge {
+                instruction: n as u32 + 1,
+                size: code.len(),
+                limit,
+            });
+            return;
+        }
+        code.resize(limit as usize, 0);
+    }
+
+    // Verifies that all the registers used by the instruction exist in the VM.
+    fn check_registers(&mut self, i: &AssemblyInstruction) {
+        for operand in &[&i.operand1, &i.operand2, &i.operand3] {
+            if let Some(Token::Register(register)) = operand {
+                if *register as usize >= MAX_REGISTERS {
+                    self.errors.push(AssemblerError::InvalidRegister {
+                        instruction: self.current_instruction,
+                        register: *register,
+                    });
+                }
+            }
+        }
+    }
+
+    // Verifies that the immediate operands fit the instruction. They take 16
+    // bits, except for LOADW, so both signed and unsigned 16-bit values are
+    // accepted. Negative values are encoded in two's complement.
+    fn check_immediates(&mut self, i: &AssemblyInstruction) {
+        if !i.has_opcode() || i.encoded_opcode() == Some(Opcode::LOADW) {
+            return;
+        }
+        for operand in &[&i.operand1, &i.operand2, &i.operand3] {
+            if let Some(Token::IntegerOperand(value)) = operand {
+                if *value < i32::from(i16::MIN) || *value > i32::from(u16::MAX) {
+                    self.errors.push(AssemblerError::ImmediateOutOfRange {
+                        instruction: self.current_instruction,
+                        value: *value,
+                    });
+                }
+            }
+        }
+    }
+
+    // Verifies that labels are only used in operands that can hold an
+    // address. A label in place of a register would be encoded as the
+    // register number and a 16-bit immediate can't hold a larger address.
+    fn check_label_operands(&mut self, i: &AssemblyInstruction) {
+        let opcode = match i.encoded_opcode() {
+            Some(opcode) => opcode,
+            None => return,
+        };
+        // Position independent mode encodes these as relative jumps.
+        if self.position_independent && (opcode == Opcode::JMPF || opcode == Opcode::JMPB) {
+            return;
+        }
+        // Jumps to labels don't use absolute addresses in position
+        // independent mode, so only their relative offset has to fit.
+        let absolute = !self.position_independent
+            || !matches!(
+                i.get_opcode(),
+                Some(Opcode::JMP)
+                    | Some(Opcode::CALL)
+                    | Some(Opcode::JEQ)
+                    | Some(Opcode::JNEQ)
+                    | Some(Opcode::JO)
+                    | Some(Opcode::JNO)
+            );
+        let kinds = operand_kinds(opcode);
+        for (n, operand) in [&i.operand1, &i.operand2, &i.operand3].iter().enumerate() {
+            let label = match operand {
+                Some(Token::LabelUsage(label)) => label,
+                _ => continue,
+            };
+            let offset = self.symbol_table.get(label).map_or(0, |info| info.offset());
+            match kinds.get(n) {
+                Some(OperandKind::Register) => {
+                    self.errors.push(AssemblerError::LabelAsRegister {
+                        instruction: self.current_instruction,
+                        label: label.clone(),
+                    });
+                }
+                Some(OperandKind::Immediate) if absolute && offset > u32::from(u16::MAX) => {
+                    self.errors.push(AssemblerError::LabelOutOfRange {
+                        instruction: self.current_instruction,
+                        label: label.clone(),
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Verifies that a used label is declared and, for jumps, that it points
+    // somewhere inside the code section.
+    fn check_label_usage(&mut self, i: &AssemblyInstruction, label: String, code_end: usize) {
+        let instruction = self.current_instruction;
+        let (offset, symbol_type) = match self.symbol_table.get(&label) {
+            Some(info) => (info.offset(), info.symbol_type()),
+            None => {
+                self.errors
+                    .push(AssemblerError::UndefinedLabel { instruction, label });
+                return;
+            }
+        };
+
+        match i.get_opcode() {
+            Some(Opcode::JMP) | Some(Opcode::JEQ) | Some(Opcode::JNEQ) | Some(Opcode::JO)
+            | Some(Opcode::JNO) | Some(Opcode::CALL) => (),
+            _ => return,
+        }
+
+        let warning = if symbol_type != SymbolType::Label {
+            AssemblerWarning::JumpIntoData { instruction, label }
+        } else if offset as usize >= code_end {
+            AssemblerWarning::JumpOutOfRange {
+                instruction,
+                label,
+                offset,
+            }
+        } else {
+            return;
+        };
+        self.warn(warning);
+    }
+
+    // Counts PUSH and POP instructions in straight-line code ending with RET
+    // and warns if the stack doesn't end up where it started. Code ending
+    // with HLT isn't a routine so it's allowed to leave values behind. This
+    // doesn't follow jumps so it's only a heuristic.
+    fn check_stack_balance(&mut self, prog: &Program) {
+        let mut depth = 0;
+        for (n, i) in prog.instructions.iter().enumerate() {
+            let instruction = n as u32 + 1;
+            match i.get_opcode() {
+                Some(Opcode::PUSH) => depth += 1,
+                Some(Opcode::POP) => {
+                    depth -= 1;
+                    if depth < 0 {
+                        self.warn(AssemblerWarning::StackUnderflow { instruction });
+                        depth = 0;
+                    }
+                }
+                Some(Opcode::RET) => {
+                    if depth != 0 {
+                        self.warn(AssemblerWarning::StackLeak { instruction, depth });
+                    }
+                    depth = 0;
+                }
+                Some(Opcode::HLT) | Some(Opcode::HALTR) => depth = 0,
+                _ => (),
+            }
+        }
+    }
+
+    // Verifies that the last instruction of the program either halts or
+    // jumps away instead of letting the VM run off the end.
+    fn check_terminated(&mut self, prog: &Program) {
+        let last = prog
+            .instructions
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, i)| i.has_opcode());
+        if let Some((n, i)) = last {
+            match i.get_opcode() {
+                Some(Opcode::HLT) | Some(Opcode::HALTR) | Some(Opcode::JMP)
+                | Some(Opcode::JMPF) | Some(Opcode::JMPB) | Some(Opcode::RET) => (),
+                _ => self.warn(AssemblerWarning::MissingHalt {
+                    instruction: n as u32 + 1,
+                }),
+            }
+        }
+    }
+
+    // Appends the value declared by a .word or .long directive to the data
+    // section after verifying that it fits the declared width.
+    fn emit_data(&mut self, i: &AssemblyInstruction) {
+        let directive = match i.get_directive() {
+            Some(directive) => directive,
+            None => return,
+        };
+        let value = match i.operand1 {
+            Some(Token::IntegerOperand(value)) => value,
+            _ => 0,
+        };
+
+        match directive.as_str() {
+            "word" => {
+                if value < i32::from(i16::MIN) || value > i32::from(u16::MAX) {
+                    self.errors.push(AssemblerError::DataOutOfRange {
+                        instruction: self.current_instruction,
+                        directive,
+                        value,
+                    });
+                    return;
+                }
+                self.data.extend_from_slice(&(value as u16).to_be_bytes());
+            }
+            "long" => self.data.extend_from_slice(&value.to_be_bytes()),
+            "asciiz" => {
+                if let Some(bytes) = i.asciiz_bytes() {
+                    self.data.extend(bytes);
+                }
+            }
+            "align" => match i.alignment() {
+                Some(alignment) if self.in_data_section() => {
+                    let offset = self.data.len() as u32;
+                    let len = self.data.len() + padding(offset, alignment) as usize;
+                    self.data.resize(len, 0);
+                }
+                Some(_) => (),
+                None => self.errors.push(AssemblerError::InvalidAlignment {
+                    instruction: self.current_instruction,
+                    alignment: value,
+                }),
+            },
+            _ => (),
+        }
+    }
+
+    fn warn(&mut self, warning: AssemblerWarning) {
+        if self.strict {
+            self.errors.push(AssemblerError::Strict(warning));
+        } else {
+            self.warnings.push(warning);
+        }
+    }
+}
+
+// Number of bytes needed to move offset up to the next multiple of alignment.
+fn padding(offset: u32, alignment: u32) -> u32 {
+    (alignment - offset % alignment) % alignment
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vm::HaltReason;
 
     #[test]
     fn test_assemble() {
@@ -195,9 +851,937 @@ mod tests {
         let program = assembler.assemble(prog_string).unwrap();
         let mut vm = VM::new();
         vm.add_bytes(&program);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.register(0), 20);
         assert_eq!(vm.register(1), 30);
         assert_eq!(vm.register(2), 50);
     }
+
+    #[test]
+    fn test_cached_header() {
+        let assembler = Assembler::new();
+        assert_eq!(assembler.header(), &Assembler::generate_header()[..]);
+        assert_eq!(&assembler.header()[0..4], &BIN_HEADER_PREFIX);
+
+        let program = Assembler::new().assemble("hlt").unwrap();
+        assert_eq!(&program[..BIN_HEADER_LENGTH], assembler.header());
+    }
+
+    #[test]
+    fn test_assemble_with_listing() {
+        let prog_string = r##"load $0 #20
+                 .data
+                 .code
+                 loop: add $0 $0 $1
+                 jmp @loop"##;
+
+        let mut assembler = Assembler::new();
+        let (program, listing) = assembler.assemble_with_listing(prog_string).unwrap();
+        assert_eq!(program, assembler.assemble(prog_string).unwrap());
+
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0040  01 00 00 14  load $0 #20",
+                "0044               .data",
+                "0044               .code",
+                "0044  02 00 00 01  loop: add $0 $0 $1",
+                "0048  37 00 44 ff  jmp @loop",
+            ]
+        );
+
+        assert_eq!(
+            assembler.assemble_with_listing("jmp @nowhere"),
+            Err(AssemblerError::UndefinedLabel {
+                instruction: 1,
+                label: "nowhere".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_forward_label_offset() {
+        // Pass 1 records the offset of a label declared after its use and
+        // to_bytes reads it from the same symbol table.
+        let mut assembler = Assembler::new();
+        let program = assembler
+            .assemble("load $0 @end\nload $1 #1\nend: hlt")
+            .unwrap();
+        let end = assembler.symbol_table.get("end").unwrap();
+        assert_eq!(end.symbol_type(), SymbolType::Label);
+        assert_eq!(end.offset() as usize, BIN_HEADER_LENGTH + 8);
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 2..BIN_HEADER_LENGTH + 4],
+            &(end.offset() as u16).to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn test_jump_to_label() {
+        let prog_string = r##"load $0 #1
+                 jmp @skip
+                 load $0 #99
+                 skip: load $1 #2
+                 hlt"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+        let skip = BIN_HEADER_LENGTH + 12;
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 4..BIN_HEADER_LENGTH + 8],
+            &[Opcode::JMPI as u8, 0, skip as u8, PADDING]
+        );
+
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run_steps_reporting(2, |_| ()), Ok(None));
+        assert_eq!(vm.pc(), skip);
+        vm.run().unwrap();
+        assert_eq!(vm.register(0), 1);
+        assert_eq!(vm.register(1), 2);
+
+        let prog_string = r##"load $0 #0
+                 load $1 #1
+                 loop: add $0 $1 $0
+                 jmp @loop"##;
+
+        let program = assembler.assemble(prog_string).unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run_steps_reporting(4, |_| ()), Ok(None));
+        assert_eq!(vm.pc(), BIN_HEADER_LENGTH + 8);
+        assert_eq!(vm.run_steps_reporting(4, |_| ()), Ok(None));
+        assert_eq!(vm.register(0), 3);
+    }
+
+    #[test]
+    fn test_conditional_jump_to_label() {
+        let prog_string = r##"load $0 #1
+                 load $1 #1
+                 eq $0 $1
+                 jeq @skip
+                 load $2 #99
+                 skip: load $3 #7
+                 jneq @end
+                 load $4 #5
+                 end: hlt"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+        let skip = BIN_HEADER_LENGTH + 20;
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 12..BIN_HEADER_LENGTH + 16],
+            &[Opcode::JEQI as u8, 0, skip as u8, PADDING]
+        );
+
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(2), 0);
+        assert_eq!(vm.register(3), 7);
+        assert_eq!(vm.register(4), 5);
+    }
+
+    #[test]
+    fn test_label_as_register() {
+        let mut assembler = Assembler::new();
+        assert!(assembler
+            .assemble("loop: load $0 #1\njpos $0 @loop\njmpf @loop\nhlt")
+            .is_err());
+        assert_eq!(
+            assembler.errors(),
+            &[
+                AssemblerError::LabelAsRegister {
+                    instruction: 2,
+                    label: "loop".to_string()
+                },
+                AssemblerError::LabelAsRegister {
+                    instruction: 3,
+                    label: "loop".to_string()
+                }
+            ]
+        );
+        assert_eq!(
+            assembler.errors()[0].to_string(),
+            "Instruction #2: @loop can't be used in place of a register"
+        );
+
+        // Loading the address of a label is fine.
+        assert!(assembler.assemble("loop: load $0 @loop\njmp $0").is_ok());
+    }
+
+    #[test]
+    fn test_label_out_of_range() {
+        let source = format!("jmp @end\n{}end: hlt", "nop\n".repeat(16400));
+        let mut assembler = Assembler::new();
+        assert!(assembler.assemble(&source).is_err());
+        assert_eq!(
+            assembler.errors(),
+            &[AssemblerError::LabelOutOfRange {
+                instruction: 1,
+                label: "end".to_string()
+            }]
+        );
+        assert_eq!(
+            assembler.errors()[0].to_string(),
+            "Instruction #1: @end is too far away for a 16-bit address"
+        );
+
+        // LOADW holds the full 32-bit address.
+        let source = format!("loadw $0 @end\n{}end: hlt", "nop\n".repeat(16400));
+        assert!(assembler.assemble(&source).is_ok());
+    }
+
+    #[test]
+    fn test_call_label() {
+        let prog_string = r##"load $0 #1
+                 load $2 #1
+                 call @bump
+                 load $1 #9
+                 hlt
+                 bump: add $0 $2 $0
+                 ret"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+        let bump = BIN_HEADER_LENGTH + 20;
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 8..BIN_HEADER_LENGTH + 12],
+            &[Opcode::CALLI as u8, 0, bump as u8, PADDING]
+        );
+
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run_steps_reporting(3, |_| ()), Ok(None));
+        assert_eq!(vm.pc(), bump);
+        assert_eq!(vm.call_depth(), 1);
+
+        // RET comes back to the instruction right after the call.
+        assert_eq!(vm.run_steps_reporting(2, |_| ()), Ok(None));
+        assert_eq!(vm.pc(), BIN_HEADER_LENGTH + 12);
+        assert_eq!(vm.call_depth(), 0);
+        assert_eq!(vm.register(0), 2);
+
+        vm.run().unwrap();
+        assert_eq!(vm.register(1), 9);
+    }
+
+    #[test]
+    fn test_load_label_address() {
+        let prog_string = r##"load $0 @target
+                 jmp $0
+                 hlt
+                 target: load $1 #7
+                 hlt"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+
+        // target is the fourth instruction right after the header.
+        let target = BIN_HEADER_LENGTH + 12;
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 2..BIN_HEADER_LENGTH + 4],
+            &(target as u16).to_be_bytes()
+        );
+
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        vm.run().unwrap();
+        assert_eq!(vm.register(0), target as i32);
+        assert_eq!(vm.register(1), 7);
+    }
+
+    #[test]
+    fn test_wide_instruction_offsets() {
+        let prog_string = r##"load $0 #1
+                 loadw $1 #100000
+                 first: load $2 #2
+                 loadw $3 #200000
+                 second: hlt"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+        assert_eq!(program.len(), BIN_HEADER_LENGTH + 28);
+        assert_eq!(
+            assembler.symbol_table.get("first").unwrap().offset(),
+            BIN_HEADER_LENGTH as u32 + 12
+        );
+        assert_eq!(
+            assembler.symbol_table.get("second").unwrap().offset(),
+            BIN_HEADER_LENGTH as u32 + 24
+        );
+
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(1), 100_000);
+        assert_eq!(vm.register(3), 200_000);
+    }
+
+    #[test]
+    fn test_loadw_label() {
+        let prog_string = r##".data
+                 first: .word #5
+                 msg: .word #7
+                 .code
+                 loadw $1 @msg
+                 hlt"##;
+
+        let program = Assembler::new().assemble(prog_string).unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(1), 2);
+    }
+
+    #[test]
+    fn test_asciiz() {
+        let prog_string = r##".data
+                 greeting: .asciiz "Hi\n"
+                 count: .word #7
+                 .code
+                 load $0 @count
+                 hlt"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+        let greeting = assembler.symbol_table.get("greeting").unwrap();
+        assert_eq!(greeting.offset(), 0);
+        assert_eq!(greeting.symbol_type(), SymbolType::String);
+        assert_eq!(assembler.symbol_table.get("count").unwrap().offset(), 4);
+
+        // The data section follows the code.
+        let data_start = BIN_HEADER_LENGTH + 8;
+        assert_eq!(&program[data_start..], &[b'H', b'i', b'\n', 0, 0, 7]);
+        assert_eq!(
+            BinaryHeader::parse(&program).unwrap().data_start as usize,
+            data_start
+        );
+
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(0), 4);
+        assert_eq!(&vm.heap()[..4], b"Hi\n\0");
+    }
+
+    #[test]
+    fn test_jump_into_data() {
+        let prog_string = r##".data
+                 buffer: .asciiz "Hello"
+                 .code
+                 jmp @buffer
+                 hlt"##;
+
+        let mut assembler = Assembler::new();
+        assert_eq!(assembler.assemble(prog_string).is_ok(), true);
+        assert_eq!(
+            assembler.warnings(),
+            &[AssemblerWarning::JumpIntoData {
+                instruction: 4,
+                label: "buffer".to_string()
+            }]
+        );
+
+        let mut assembler = Assembler::new();
+        assembler.set_strict(true);
+        assert_eq!(assembler.assemble(prog_string).is_err(), true);
+        assert_eq!(assembler.errors().len(), 1);
+    }
+
+    #[test]
+    fn test_jump_into_code() {
+        let prog_string = r##"start: load $0 #1
+                 jmp @start
+                 hlt"##;
+
+        let mut assembler = Assembler::new();
+        assert_eq!(assembler.assemble(prog_string).is_ok(), true);
+        assert_eq!(assembler.warnings().is_empty(), true);
+    }
+
+    #[test]
+    fn test_run_passes_individually() {
+        let prog_string = r##"load $0 #1
+                 loop: inc $0
+                 jmp @loop"##;
+        let (_, program) =
+            parsers::parse_program(&AssemblerSyntax::default(), prog_string).unwrap();
+
+        let mut assembler = Assembler::new();
+        let symbols = assembler.run_pass1_only(&program);
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(
+            symbols.get("loop").unwrap().offset(),
+            BIN_HEADER_LENGTH as u32 + 4
+        );
+
+        let executable = assembler.run_pass2_only(&program).unwrap();
+        assert_eq!(executable, assembler.assemble(prog_string).unwrap());
+        assert_eq!(
+            &executable[BIN_HEADER_LENGTH + 8..],
+            &[Opcode::JMPI as u8, 0, BIN_HEADER_LENGTH as u8 + 4, 0xFF]
+        );
+    }
+
+    #[test]
+    fn test_resolve_symbols() {
+        let prog_string = r##"start: load $0 #1
+                 loadw $1 #100000
+                 middle: inc $0
+                 end: hlt
+                 .data
+                 msg: .asciiz "Hi"
+                 "##;
+
+        let mut assembler = Assembler::new();
+        let symbols = assembler.resolve_symbols(prog_string).unwrap();
+        assert_eq!(symbols.len(), 4);
+
+        let header = BIN_HEADER_LENGTH as u32;
+        for (name, offset) in &[
+            ("start", header),
+            ("middle", header + 12),
+            ("end", header + 16),
+        ] {
+            let info = symbols.get(*name).unwrap();
+            assert_eq!(info.offset(), *offset);
+            assert_eq!(info.symbol_type(), SymbolType::Label);
+        }
+        assert_eq!(
+            symbols.get("msg").unwrap().symbol_type(),
+            SymbolType::String
+        );
+    }
+
+    #[test]
+    fn test_constant_expression() {
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble("load $0 #(3*4+1)\nhlt").unwrap();
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH..BIN_HEADER_LENGTH + 4],
+            &[Opcode::LOAD as u8, 0, 0, 13]
+        );
+    }
+
+    #[test]
+    fn test_shift_immediate() {
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble("shli $0 #4\nshri $1 #40\nhlt").unwrap();
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH..BIN_HEADER_LENGTH + 8],
+            &[Opcode::SHLI as u8, 0, 0, 4, Opcode::SHRI as u8, 1, 0, 40]
+        );
+    }
+
+    #[test]
+    fn test_data_words() {
+        let prog_string = r##"hlt
+                 .data
+                 first: .word #-1
+                 second: .long #-70000
+                 third: .word #65535"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 4..],
+            &[0xFF, 0xFF, 0xFF, 0xFE, 0xEE, 0x90, 0xFF, 0xFF]
+        );
+        assert_eq!(assembler.symbol_table.get("second").unwrap().offset(), 2);
+        assert_eq!(assembler.symbol_table.get("third").unwrap().offset(), 6);
+    }
+
+    #[test]
+    fn test_forward_data_reference() {
+        let prog_string = r##"load $0 @buffer
+                 load $1 @end
+                 hlt
+                 .data
+                 padding: .long #0
+                 buffer: .word #42
+                 end: .word #0"##;
+
+        let mut assembler = Assembler::new();
+        assembler.set_strict(true);
+        let program = assembler.assemble(prog_string).unwrap();
+        assert!(assembler.warnings().is_empty());
+
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(0), 4);
+        assert_eq!(vm.register(1), 6);
+        assert_eq!(&vm.heap()[4..6], &[0, 42]);
+    }
+
+    #[test]
+    fn test_interleaved_sections() {
+        let prog_string = r##".code
+                 load $0 @second
+                 .data
+                 first: .word #1
+                 .code
+                 middle: load $1 @first
+                 .data
+                 second: .asciiz "ab"
+                 .code
+                 hlt"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+        let code_start = BIN_HEADER_LENGTH;
+        let data_start = code_start + 12;
+
+        let header = BinaryHeader::parse(&program).unwrap();
+        assert_eq!(header.code_start as usize, code_start);
+        assert_eq!(header.data_start as usize, data_start);
+
+        // Instructions end up in the code region and declarations in the data
+        // region regardless of how the sections are interleaved.
+        let load = Opcode::LOAD as u8;
+        assert_eq!(
+            &program[code_start..data_start],
+            &[
+                load,
+                0,
+                0,
+                2,
+                load,
+                1,
+                0,
+                0,
+                Opcode::HLT as u8,
+                PADDING,
+                PADDING,
+                PADDING
+            ]
+        );
+        assert_eq!(&program[data_start..], &[0, 1, b'a', b'b', 0]);
+        assert_eq!(
+            assembler.symbol_table.get("middle").unwrap().offset() as usize,
+            code_start + 4
+        );
+        assert_eq!(assembler.sections().len(), 5);
+    }
+
+    #[test]
+    fn test_multiple_data_segments() {
+        let prog_string = r##".data
+                 first: .word #1
+                 .code
+                 load $1 @second
+                 hlt
+                 .data
+                 second: .long #-2"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+        assert_eq!(assembler.symbol_table.get("first").unwrap().offset(), 0);
+        assert_eq!(assembler.symbol_table.get("second").unwrap().offset(), 2);
+
+        let header = BinaryHeader::parse(&program).unwrap();
+        assert_eq!(header.data_start as usize, BIN_HEADER_LENGTH + 8);
+
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        vm.run().unwrap();
+        assert_eq!(vm.heap(), &[0, 1, 0xFF, 0xFF, 0xFF, 0xFE]);
+
+        // $1 holds the heap offset of the second segment's label.
+        let offset = vm.register(1) as usize;
+        assert_eq!(&vm.heap()[offset..offset + 4], &(-2i32).to_be_bytes());
+    }
+
+    #[test]
+    fn test_data_word_out_of_range() {
+        let mut assembler = Assembler::new();
+        assert!(assembler.assemble("hlt\n.data\n.word #-70000").is_err());
+        assert_eq!(
+            assembler.errors(),
+            &[AssemblerError::DataOutOfRange {
+                instruction: 3,
+                directive: "word".to_string(),
+                value: -70000
+            }]
+        );
+        assert_eq!(
+            assembler.errors()[0].to_string(),
+            "Instruction #3: value -70000 doesn't fit in .word"
+        );
+    }
+
+    #[test]
+    fn test_align_data() {
+        let prog_string = r##"hlt
+                 .data
+                 first: .word #1
+                 second: .word #2
+                 third: .word #3
+                 .align #8
+                 fourth: .long #4
+                 .align #8
+                 fifth: .word #5"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 4..],
+            &[0, 1, 0, 2, 0, 3, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 5]
+        );
+        assert_eq!(assembler.symbol_table.get("fourth").unwrap().offset(), 8);
+        assert_eq!(assembler.symbol_table.get("fifth").unwrap().offset(), 16);
+    }
+
+    #[test]
+    fn test_align_code() {
+        let prog_string = r##"load $0 #1
+                 .align #16
+                 target: hlt"##;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(prog_string).unwrap();
+        let target = assembler.symbol_table.get("target").unwrap().offset() as usize;
+        assert_eq!(target, BIN_HEADER_LENGTH + 16);
+        for gap in program[BIN_HEADER_LENGTH + 4..target].chunks(4) {
+            assert_eq!(gap, &[Opcode::NOP as u8, PADDING, PADDING, PADDING]);
+        }
+        assert_eq!(program[target], Opcode::HLT as u8);
+
+        // Execution falls through the gap.
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.pc(), target + 1);
+        assert_eq!(vm.register(0), 1);
+    }
+
+    #[test]
+    fn test_align_not_power_of_two() {
+        let mut assembler = Assembler::new();
+        assert!(assembler.assemble("hlt\n.data\n.align #6").is_err());
+        assert_eq!(
+            assembler.errors(),
+            &[AssemblerError::InvalidAlignment {
+                instruction: 3,
+                alignment: 6
+            }]
+        );
+    }
+
+    #[test]
+    fn test_non_ascii_parse_error() {
+        let mut assembler = Assembler::new();
+        assert_eq!(
+            assembler.assemble("hlt\né\n"),
+            Err(AssemblerError::ParseError {
+                line: 2,
+                fragment: "é".to_string()
+            })
+        );
+        assert_eq!(
+            assembler.assemble("load $0 #1\nlöad $1 #2\nhlt"),
+            Err(AssemblerError::ParseError {
+                line: 2,
+                fragment: "löad $1 #2".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_trailing_input() {
+        // An illegal token in the middle of the program must not produce a
+        // binary made of the instructions before it.
+        let prog_string = "load $0 #1\nload $1 #2\nadd $0 $1 $2 !\nload $3 #4\nhlt";
+        let mut assembler = Assembler::new();
+        assert_eq!(
+            assembler.assemble(prog_string),
+            Err(AssemblerError::ParseError {
+                line: 3,
+                fragment: "add $0 $1 $2 !".to_string()
+            })
+        );
+
+        // Trailing whitespace and comments are fine.
+        assert!(assembler.assemble("load $0 #1\nhlt\n  \n").is_ok());
+        assert_eq!(
+            assembler
+                .assemble("; nothing to see here")
+                .map(|program| program.len()),
+            Ok(BIN_HEADER_LENGTH)
+        );
+    }
+
+    #[test]
+    fn test_parse_and_semantic_errors() {
+        let mut assembler = Assembler::new();
+        // The parser stops at the second line, leaving the rest unconsumed.
+        let error = AssemblerError::ParseError {
+            line: 2,
+            fragment: "foo $0".to_string(),
+        };
+        assert_eq!(
+            assembler.assemble("load $0 #1\nfoo $0\nhlt"),
+            Err(error.clone())
+        );
+        assert_eq!(error.to_string(), "Line #2: failed to parse `foo $0`");
+        assert_eq!(assembler.errors(), std::slice::from_ref(&error));
+        assert_eq!(
+            assembler.resolve_symbols("load $0 #1\nfoo $0\nhlt").err(),
+            Some(error)
+        );
+
+        // Input that can't be parsed at all is reported as well.
+        assert!(assembler.assemble("$0").is_err());
+        assert!(matches!(
+            assembler.errors(),
+            [AssemblerError::ParseError { line: 1, .. }]
+        ));
+
+        assert_eq!(
+            assembler.assemble("jmp @nowhere\nhlt"),
+            Err(AssemblerError::UndefinedLabel {
+                instruction: 1,
+                label: "nowhere".to_string()
+            })
+        );
+        assert_eq!(assembler.errors().len(), 1);
+    }
+
+    #[test]
+    fn test_code_size() {
+        let mut assembler = Assembler::new();
+        let program = assembler
+            .assemble(".size #128\nload $0 #7\nhlt\n.data\n.word #1")
+            .unwrap();
+        assert_eq!(program.len(), BIN_HEADER_LENGTH + 128 + 2);
+        assert!(program[BIN_HEADER_LENGTH + 8..BIN_HEADER_LENGTH + 128]
+            .iter()
+            .all(|&b| b == 0));
+
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(0), 7);
+        assert_eq!(vm.heap(), &[0, 1]);
+
+        assert!(assembler.assemble(".size #4\nload $0 #7\nhlt").is_err());
+        assert_eq!(
+            assembler.errors(),
+            &[AssemblerError::CodeTooLarge {
+                instruction: 1,
+                size: 8,
+                limit: 4
+            }]
+        );
+    }
+
+    #[test]
+    fn test_position_independent_jumps() {
+        let prog_string = r##"load $0 #3
+                 load $2 #1
+                 jmpf @loop
+                 load $1 #99
+                 loop: sub $0 $2 $0
+                 jmpb @loop"##;
+
+        let mut assembler = Assembler::new();
+        assembler.set_position_independent(true);
+        let program = assembler.assemble(prog_string).unwrap();
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 8..BIN_HEADER_LENGTH + 12],
+            &[Opcode::JMPFI as u8, 0, 8, PADDING]
+        );
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 20..],
+            &[Opcode::JMPBI as u8, 0, 4, PADDING]
+        );
+
+        // Run up to the backward jump. The forward jump skips over LOAD $1.
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        let result = vm.run_steps_reporting(5, |_| ());
+        assert_eq!(result, Ok(None));
+        assert_eq!(vm.pc(), BIN_HEADER_LENGTH + 16);
+        assert_eq!(vm.register(0), 2);
+        assert_eq!(vm.register(1), 0);
+
+        assert!(assembler.assemble("loop: hlt\njmpf @loop").is_err());
+        assert_eq!(
+            assembler.errors(),
+            &[AssemblerError::RelativeJumpOutOfRange {
+                instruction: 2,
+                label: "loop".to_string()
+            }]
+        );
+
+        // Plain jumps go in the direction of the label.
+        let program = assembler
+            .assemble("jmp @end\nback: hlt\nend: jmp @back")
+            .unwrap();
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH..BIN_HEADER_LENGTH + 4],
+            &[Opcode::JMPFI as u8, 0, 8, PADDING]
+        );
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 8..],
+            &[Opcode::JMPBI as u8, 0, 4, PADDING]
+        );
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.cycles(), 3);
+
+        // Calls and conditional jumps only have absolute forms.
+        assert!(assembler.assemble("f: ret\ncall @f\njeq @f").is_err());
+        assert_eq!(
+            assembler.errors(),
+            &[
+                AssemblerError::AbsoluteJump {
+                    instruction: 2,
+                    label: "f".to_string()
+                },
+                AssemblerError::AbsoluteJump {
+                    instruction: 3,
+                    label: "f".to_string()
+                }
+            ]
+        );
+        assert_eq!(
+            assembler.errors()[0].to_string(),
+            "Instruction #2: jump to @f isn't position independent"
+        );
+    }
+
+    #[test]
+    fn test_check_stack_balance() {
+        let balanced = r##"push $0
+                 push $1
+                 pop $1
+                 pop $0
+                 ret
+                 push $0
+                 hlt"##;
+        let mut assembler = Assembler::new();
+        assembler.set_check_stack(true);
+        assert!(assembler.assemble(balanced).is_ok());
+        assert!(assembler.warnings().is_empty());
+
+        let unbalanced = r##"push $0
+                 push $1
+                 pop $1
+                 ret
+                 pop $0
+                 ret"##;
+        assert!(assembler.assemble(unbalanced).is_ok());
+        assert_eq!(
+            assembler.warnings(),
+            &[
+                AssemblerWarning::StackLeak {
+                    instruction: 4,
+                    depth: 1
+                },
+                AssemblerWarning::StackUnderflow { instruction: 5 },
+            ]
+        );
+
+        // The check is opt-in.
+        assembler.set_check_stack(false);
+        assert!(assembler.assemble(unbalanced).is_ok());
+        assert!(assembler.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_missing_halt() {
+        let mut assembler = Assembler::new();
+        assert!(assembler.assemble("load $0 #1\nload $1 #2").is_ok());
+        assert_eq!(
+            assembler.warnings(),
+            &[AssemblerWarning::MissingHalt { instruction: 2 }]
+        );
+
+        assert!(assembler.assemble("load $0 #1\nhlt").is_ok());
+        assert!(assembler.warnings().is_empty());
+
+        assert!(assembler.assemble("start: load $0 #1\njmp @start").is_ok());
+        assert!(assembler.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_immediate_out_of_range() {
+        let mut assembler = Assembler::new();
+        assert!(assembler
+            .assemble("load $0 #-32768\nload $1 #65535")
+            .is_ok());
+
+        assert!(assembler.assemble("load $0 #1\nshli $1 #70000").is_err());
+        assert_eq!(
+            assembler.errors(),
+            &[AssemblerError::ImmediateOutOfRange {
+                instruction: 2,
+                value: 70000
+            }]
+        );
+        assert_eq!(
+            assembler.errors()[0].to_string(),
+            "Instruction #2: immediate 70000 doesn't fit in 16 bits"
+        );
+
+        assert!(assembler.assemble("shli $0 #-32769").is_err());
+
+        // LOADW carries a full 32-bit immediate and LOAD is widened to it.
+        assert!(assembler.assemble("loadw $0 #-70000").is_ok());
+        assert!(assembler.assemble("load $0 #-70000").is_ok());
+    }
+
+    #[test]
+    fn test_invalid_register() {
+        let mut assembler = Assembler::new();
+        assert!(assembler.assemble("load $0 #1\nadd $0 $1 $40").is_err());
+        assert_eq!(
+            assembler.errors(),
+            &[AssemblerError::InvalidRegister {
+                instruction: 2,
+                register: 40
+            }]
+        );
+        assert_eq!(
+            assembler.errors()[0].to_string(),
+            "Instruction #2: register $40 is out of range (max $31)"
+        );
+
+        assert!(assembler.assemble("load $40 #1").is_err());
+        assert_eq!(
+            assembler.errors(),
+            &[AssemblerError::InvalidRegister {
+                instruction: 1,
+                register: 40
+            }]
+        );
+
+        // Numbers that don't fit in a byte can't be parsed as registers.
+        assert_eq!(
+            assembler.assemble("hlt\nload $300 #1"),
+            Err(AssemblerError::ParseError {
+                line: 2,
+                fragment: "load $300 #1".to_string()
+            })
+        );
+
+        assert!(assembler.assemble("add $0 $1 $31").is_ok());
+    }
+
+    #[test]
+    fn test_undefined_label() {
+        let mut assembler = Assembler::new();
+        assert_eq!(assembler.assemble("jmp @nowhere").is_err(), true);
+        assert_eq!(
+            assembler.errors(),
+            &[AssemblerError::UndefinedLabel {
+                instruction: 1,
+                label: "nowhere".to_string()
+            }]
+        );
+    }
 }
@@ -1,10 +1,11 @@
 use nom::branch::alt;
 use nom::bytes::complete::{escaped, is_not, tag};
-use nom::character::complete::{alpha1, alphanumeric1, digit1, one_of};
-use nom::combinator::{cut, map, opt};
-use nom::multi::many1;
-use nom::sequence::{delimited, preceded, terminated, tuple};
+use nom::character::complete::{alpha1, alphanumeric1, char, digit1, hex_digit1, one_of, space0};
+use nom::combinator::{cut, map, map_opt, map_res, opt, recognize, verify};
+use nom::multi::many0;
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 use nom::IResult;
+use std::convert::TryFrom;
 
 use nom::error::context;
 
@@ -15,66 +16,196 @@ use crate::opcode::Opcode;
 
 type ParseResult<'a, T> = IResult<&'a str, T>;
 
-/// Parses opcode part of the instruction.
-fn parse_opcode(input: &str) -> ParseResult<Token> {
-    let (next_input, result) = alpha1(input.trim())?;
-    Ok((next_input, Token::Opcode(Opcode::from(result))))
+/// Lexical conventions of an assembly dialect. The parser entry points take
+/// the syntax so that the same parser can be reused for other dialects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssemblerSyntax {
+    /// Starts a comment that runs till the end of the line i.e. `;`.
+    pub comment_prefix: String,
+
+    /// Terminates a label declaration i.e. `:`. If it's empty, any leading
+    /// word that isn't a mnemonic is taken as a label.
+    pub label_suffix: String,
+
+    /// Starts an integer operand i.e. `#`.
+    pub immediate_prefix: String,
+}
+
+impl Default for AssemblerSyntax {
+    fn default() -> Self {
+        AssemblerSyntax {
+            comment_prefix: ";".to_string(),
+            label_suffix: ":".to_string(),
+            immediate_prefix: "#".to_string(),
+        }
+    }
+}
+
+/// Parses opcode part of the instruction. The whole word has to be a known
+/// mnemonic so that e.g. `jmpx` isn't taken for something else.
+fn parse_opcode(input: &str) -> ParseResult<'_, Token> {
+    map_res(context("opcode", alpha1), |mnemonic| {
+        Opcode::try_from(mnemonic).map(Token::Opcode)
+    })(input.trim())
 }
 
 /// Parses the register part. i.e. $0. We don't enforce the register
-/// count limit here. It'll be taken care of at the assembler level.
-fn parse_register(input: &str) -> ParseResult<Token> {
+/// count limit here. It'll be taken care of at the assembler level, but
+/// numbers that don't even fit in a byte are rejected.
+fn parse_register(input: &str) -> ParseResult<'_, Token> {
     map(
-        context("register", preceded(tag("$"), cut(digit1))),
-        |num: &str| Token::Register(num.parse::<u8>().unwrap()),
+        context(
+            "register",
+            preceded(
+                tag("$"),
+                cut(map_res(digit1, |num: &str| num.parse::<u8>())),
+            ),
+        ),
+        Token::Register,
     )(input.trim())
 }
 
-/// Parses the number operand #123.
-fn parse_number(input: &str) -> ParseResult<Token> {
-    map(
-        context("integer", preceded(tag("#"), cut(digit1))),
-        |num: &str| Token::IntegerOperand(num.parse::<i32>().unwrap()),
+/// Parses the number operand #123, #-123, #0x1F, #0b1010 or a constant
+/// expression like #(3*4+1). Hex and binary literals may use all 32 bits.
+fn parse_number<'a>(syntax: &AssemblerSyntax, input: &'a str) -> ParseResult<'a, Token> {
+    let prefix = syntax.immediate_prefix.as_str();
+    let expression = map_opt(
+        preceded(
+            tuple((tag(prefix), char('('), space0)),
+            cut(terminated(parse_expression, pair(space0, char(')')))),
+        ),
+        |value| i32::try_from(value).ok(),
+    );
+    let radix = |radix_prefix, radix| {
+        preceded(
+            tag(radix_prefix),
+            cut(map_res(hex_digit1, move |num: &str| {
+                u32::from_str_radix(num, radix).map(|value| value as i32)
+            })),
+        )
+    };
+    let literal = preceded(
+        tag(prefix),
+        cut(alt((
+            radix("0x", 16),
+            radix("0b", 2),
+            map_res(recognize(pair(opt(tag("-")), digit1)), |num: &str| {
+                num.parse::<i32>()
+            }),
+        ))),
+    );
+
+    map(context("integer", alt((expression, literal))), |value| {
+        Token::IntegerOperand(value)
+    })(input)
+}
+
+/// Parses a constant expression made of integers, + - * / and parentheses.
+/// Values are computed using 64-bit integers so that the caller can detect
+/// results that don't fit the operand. Overflow and division by zero fail
+/// the parser.
+fn parse_expression(input: &str) -> ParseResult<'_, i64> {
+    map_opt(
+        pair(
+            parse_term,
+            many0(pair(delimited(space0, one_of("+-"), space0), parse_term)),
+        ),
+        |(first, rest)| {
+            rest.into_iter()
+                .try_fold(first, |acc, (op, value)| match op {
+                    '+' => acc.checked_add(value),
+                    _ => acc.checked_sub(value),
+                })
+        },
     )(input)
 }
 
-/// Parse quoted string literals i.e. "abc\ndef". We support the following
-/// characters to be escaped using a \ prefix
-///     \ntr
+fn parse_term(input: &str) -> ParseResult<'_, i64> {
+    map_opt(
+        pair(
+            parse_factor,
+            many0(pair(delimited(space0, one_of("*/"), space0), parse_factor)),
+        ),
+        |(first, rest)| {
+            rest.into_iter()
+                .try_fold(first, |acc, (op, value)| match op {
+                    '*' => acc.checked_mul(value),
+                    _ => acc.checked_div(value),
+                })
+        },
+    )(input)
+}
+
+fn parse_factor(input: &str) -> ParseResult<'_, i64> {
+    alt((
+        delimited(
+            pair(char('('), space0),
+            parse_expression,
+            pair(space0, char(')')),
+        ),
+        map_res(recognize(pair(opt(char('-')), digit1)), |num: &str| {
+            num.parse::<i64>()
+        }),
+    ))(input)
+}
+
+/// Parse quoted string literals i.e. "abc\ndef" or 'abc\ndef'. We support
+/// the following characters to be escaped using a \ prefix
+///     \ntr"'
 ///
-/// NOTE: For now, we don't support escaping of quote i.e. \"
-fn parse_string(input: &str) -> ParseResult<Token> {
-    let not_escaped_or_end = |s| is_not("\\\"")(s);
+/// The escape sequences are kept as is. They are resolved when the string is
+/// emitted.
+fn parse_string(input: &str) -> ParseResult<'_, Token> {
+    let quoted = |quote, not_escaped_or_end| {
+        delimited(
+            tag(quote),
+            escaped(is_not(not_escaped_or_end), '\\', one_of(r#"\ntr"'"#)),
+            tag(quote),
+        )
+    };
 
     map(
         context(
             "string literal",
-            delimited(
-                tag("\""),
-                escaped(not_escaped_or_end, '\\', one_of(r#"\ntr""#)),
-                tag("\""),
-            ),
+            alt((quoted("\"", "\\\""), quoted("'", "\\'"))),
         ),
         |s: &str| Token::StringOperand(s.to_string()),
     )(input.trim())
 }
 
 /// Parses an operand.
-fn parse_operand(input: &str) -> ParseResult<Token> {
-    alt((parse_number, parse_register, parse_string))(input.trim())
+fn parse_operand<'a>(syntax: &AssemblerSyntax, input: &'a str) -> ParseResult<'a, Token> {
+    alt((
+        |i| parse_number(syntax, i),
+        parse_register,
+        parse_string,
+        parse_label_usage,
+    ))(input.trim())
+}
+
+/// Parses an operand that follows another operand. Operands can be separated
+/// by whitespace and an optional comma i.e. add $0, $1, $2.
+fn parse_next_operand<'a>(syntax: &AssemblerSyntax, input: &'a str) -> ParseResult<'a, Token> {
+    preceded(opt(tag(",")), |i| parse_operand(syntax, i))(input.trim())
 }
 
 /// Parses a label declaration. Labels are of the form
 /// label_1: ....
-fn parse_label_declaration(input: &str) -> ParseResult<Token> {
+fn parse_label_declaration<'a>(syntax: &AssemblerSyntax, input: &'a str) -> ParseResult<'a, Token> {
+    // Without a suffix, mnemonics can't be told apart from labels.
+    let suffix = syntax.label_suffix.as_str();
+    let label = verify(alphanumeric1, move |label: &str| {
+        !suffix.is_empty() || Opcode::try_from(label).is_err()
+    });
+
     map(
-        context("label declaration", terminated(alphanumeric1, tag(":"))),
+        context("label declaration", terminated(label, tag(suffix))),
         |label: &str| Token::LabelDeclaration(label.to_string()),
     )(input.trim())
 }
 
 /// Parses label usage i.e. @label
-fn parse_label_usage(input: &str) -> ParseResult<Token> {
+fn parse_label_usage(input: &str) -> ParseResult<'_, Token> {
     map(
         context("label usage", preceded(tag("@"), alphanumeric1)),
         |label: &str| Token::LabelUsage(label.to_string()),
@@ -82,7 +213,7 @@ fn parse_label_usage(input: &str) -> ParseResult<Token> {
 }
 
 /// Parses directive declaration i.e. .code or .data or .asciiz
-fn parse_directive_declaration(input: &str) -> ParseResult<Token> {
+fn parse_directive_declaration(input: &str) -> ParseResult<'_, Token> {
     map(
         context("directive", preceded(tag("."), alphanumeric1)),
         |s: &str| Token::Directive(s.to_string()),
@@ -91,13 +222,16 @@ fn parse_directive_declaration(input: &str) -> ParseResult<Token> {
 
 /// Parses a labeled directive.
 ///  howdy: .asciiz 'Hello'
-fn parse_directive(input: &str) -> ParseResult<AssemblyInstruction> {
+fn parse_directive<'a>(
+    syntax: &AssemblerSyntax,
+    input: &'a str,
+) -> ParseResult<'a, AssemblyInstruction> {
     let parser = tuple((
-        opt(parse_label_declaration),
+        opt(|i| parse_label_declaration(syntax, i)),
         parse_directive_declaration,
-        opt(parse_operand),
-        opt(parse_operand),
-        opt(parse_operand),
+        opt(|i| parse_operand(syntax, i)),
+        opt(|i| parse_next_operand(syntax, i)),
+        opt(|i| parse_next_operand(syntax, i)),
     ));
 
     match parser(input.trim()) {
@@ -118,15 +252,18 @@ fn parse_directive(input: &str) -> ParseResult<AssemblyInstruction> {
 
 /// This is the high level instruction parser combinator that parses
 /// all forms of instructions.
-fn parse_instruction(input: &str) -> ParseResult<AssemblyInstruction> {
+fn parse_instruction<'a>(
+    syntax: &AssemblerSyntax,
+    input: &'a str,
+) -> ParseResult<'a, AssemblyInstruction> {
     // Its important that the opcode only instruction is parsed as the last resort
     // given that its format matches all other types of instructions.
     let parser = tuple((
-        opt(parse_label_declaration),
+        opt(|i| parse_label_declaration(syntax, i)),
         parse_opcode,
-        opt(parse_operand),
-        opt(parse_operand),
-        opt(parse_operand),
+        opt(|i| parse_operand(syntax, i)),
+        opt(|i| parse_next_operand(syntax, i)),
+        opt(|i| parse_next_operand(syntax, i)),
     ));
 
     match parser(input.trim()) {
@@ -145,12 +282,57 @@ fn parse_instruction(input: &str) -> ParseResult<AssemblyInstruction> {
     }
 }
 
+/// Skips whitespace and comments.
+fn skip_comments<'a>(syntax: &AssemblerSyntax, input: &'a str) -> &'a str {
+    let mut remaining = input.trim_start();
+    let prefix = syntax.comment_prefix.as_str();
+    while !prefix.is_empty() && remaining.starts_with(prefix) {
+        remaining = match remaining.find('\n') {
+            Some(end) => remaining[end..].trim_start(),
+            None => "",
+        };
+    }
+    remaining
+}
+
 /// Parses a complete program.
-pub fn parse_program(input: &str) -> ParseResult<Program> {
-    match many1(alt((parse_instruction, parse_directive)))(input.trim()) {
-        Ok((next_input, instructions)) => Ok((next_input, Program { instructions })),
-        Err(e) => Err(e),
+pub fn parse_program<'a>(syntax: &AssemblerSyntax, input: &'a str) -> ParseResult<'a, Program> {
+    map(
+        |i| parse_program_with_source(syntax, i),
+        |(program, _source)| program,
+    )(input)
+}
+
+/// Parses a complete program and also returns the source text of each
+/// instruction. It's mainly used to generate listings.
+pub fn parse_program_with_source<'a>(
+    syntax: &AssemblerSyntax,
+    input: &'a str,
+) -> ParseResult<'a, (Program, Vec<&'a str>)> {
+    let mut instructions = vec![];
+    let mut source = vec![];
+    let mut remaining = skip_comments(syntax, input);
+
+    // Programs that are made of comments only don't have any instructions.
+    while !remaining.is_empty() {
+        match alt((
+            |i| parse_instruction(syntax, i),
+            |i| parse_directive(syntax, i),
+        ))(remaining)
+        {
+            Ok((next_input, instruction)) => {
+                let consumed = &remaining[..remaining.len() - next_input.len()];
+                source.push(consumed.trim());
+                instructions.push(instruction);
+                remaining = skip_comments(syntax, next_input);
+            }
+            // Input that isn't a comment needs at least one instruction.
+            Err(nom::Err::Error(_)) if !instructions.is_empty() => break,
+            Err(e) => return Err(e),
+        }
     }
+
+    Ok((remaining, (Program { instructions }, source)))
 }
 
 #[cfg(test)]
@@ -184,6 +366,29 @@ mod tests {
             parse_opcode("hlt bla bla"),
             Ok((" bla bla", Token::Opcode(Opcode::HLT)))
         );
+
+        // Unknown mnemonics fail instead of partially matching.
+        assert!(parse_opcode("jmpx $0").is_err());
+        assert!(parse_instruction(&AssemblerSyntax::default(), "jmpx $0").is_err());
+    }
+
+    #[test]
+    fn test_parse_every_opcode() {
+        // The parser and the VM share the Opcode enum so every opcode's
+        // mnemonic has to parse back to that opcode.
+        for byte in 0..=u8::MAX {
+            let opcode = Opcode::from(byte);
+            if opcode == Opcode::IGL {
+                continue;
+            }
+            let mnemonic = format!("{:?}", opcode).to_lowercase();
+            assert_eq!(
+                parse_opcode(&mnemonic),
+                Ok(("", Token::Opcode(opcode))),
+                "{}",
+                mnemonic
+            );
+        }
     }
 
     #[test]
@@ -197,26 +402,139 @@ mod tests {
             parse_register("$a $b"),
             Err(Failure(("a $b", ErrorKind::Digit)))
         );
+        assert_eq!(
+            parse_register("$300 #1"),
+            Err(Failure(("300 #1", ErrorKind::MapRes)))
+        );
     }
 
     #[test]
     fn test_parse_number() {
-        assert_eq!(parse_number("#500"), Ok(("", Token::IntegerOperand(500))));
         assert_eq!(
-            parse_number("#1000 ;1k"),
+            parse_number(&AssemblerSyntax::default(), "#500"),
+            Ok(("", Token::IntegerOperand(500)))
+        );
+        assert_eq!(
+            parse_number(&AssemblerSyntax::default(), "#1000 ;1k"),
             Ok((" ;1k", Token::IntegerOperand(1000)))
         );
+        assert_eq!(
+            parse_number(&AssemblerSyntax::default(), "#-42"),
+            Ok(("", Token::IntegerOperand(-42)))
+        );
+        assert_eq!(
+            parse_number(&AssemblerSyntax::default(), "#-5"),
+            Ok(("", Token::IntegerOperand(-5)))
+        );
+        assert_eq!(
+            parse_number(&AssemblerSyntax::default(), "#-32768"),
+            Ok(("", Token::IntegerOperand(-32768)))
+        );
+        assert!(parse_number(&AssemblerSyntax::default(), "#-").is_err());
+
+        assert_eq!(
+            parse_number(&AssemblerSyntax::default(), "#0xFF"),
+            Ok(("", Token::IntegerOperand(255)))
+        );
+        assert_eq!(
+            parse_number(&AssemblerSyntax::default(), "#0b100"),
+            Ok(("", Token::IntegerOperand(4)))
+        );
+        assert_eq!(
+            parse_number(&AssemblerSyntax::default(), "#0xFFFFFFFF"),
+            Ok(("", Token::IntegerOperand(-1)))
+        );
+        assert!(parse_number(&AssemblerSyntax::default(), "#0xZZ").is_err());
+        assert!(parse_number(&AssemblerSyntax::default(), "#0b2").is_err());
+        assert!(parse_number(&AssemblerSyntax::default(), "#0x100000000").is_err());
+
+        // Literals that don't fit in an i32 fail instead of panicking.
+        assert!(parse_number(&AssemblerSyntax::default(), "#2147483648").is_err());
+    }
+
+    #[test]
+    fn test_parse_constant_expression() {
+        assert_eq!(
+            parse_number(&AssemblerSyntax::default(), "#(3*4+1)"),
+            Ok(("", Token::IntegerOperand(13)))
+        );
+        assert_eq!(
+            parse_number(&AssemblerSyntax::default(), "#( 2 * (3 + 4) - -1 )"),
+            Ok(("", Token::IntegerOperand(15)))
+        );
+        assert_eq!(
+            parse_number(&AssemblerSyntax::default(), "#(7/2)"),
+            Ok(("", Token::IntegerOperand(3)))
+        );
+
+        // Division by zero and values that don't fit the operand aren't folded.
+        assert!(parse_number(&AssemblerSyntax::default(), "#(1/0)").is_err());
+        assert!(parse_number(&AssemblerSyntax::default(), "#(2147483647+1)").is_err());
+
+        // Expressions involving registers aren't constant.
+        assert!(parse_number(&AssemblerSyntax::default(), "#($1+2)").is_err());
     }
     #[test]
     fn test_parse_operand() {
-        assert_eq!(parse_operand(" #99 "), Ok(("", Token::IntegerOperand(99))));
-        assert_eq!(parse_operand(" $23 "), Ok(("", Token::Register(23))));
         assert_eq!(
-            parse_operand(" \"\tabc\n\" "),
+            parse_operand(&AssemblerSyntax::default(), " #99 "),
+            Ok(("", Token::IntegerOperand(99)))
+        );
+        assert_eq!(
+            parse_operand(&AssemblerSyntax::default(), " $23 "),
+            Ok(("", Token::Register(23)))
+        );
+        assert_eq!(
+            parse_operand(&AssemblerSyntax::default(), " @loop "),
+            Ok(("", Token::LabelUsage("loop".to_string())))
+        );
+        assert_eq!(
+            parse_operand(&AssemblerSyntax::default(), " \"\tabc\n\" "),
             Ok(("", Token::StringOperand("\tabc\n".to_string())))
         );
     }
 
+    #[test]
+    fn test_parse_comma_separated_operands() {
+        let expected = || AssemblyInstruction {
+            opcode: Some(Token::Opcode(Opcode::ADD)),
+            operand1: Some(Token::Register(0)),
+            operand2: Some(Token::Register(1)),
+            operand3: Some(Token::Register(2)),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            parse_instruction(&AssemblerSyntax::default(), "add $0 $1 $2"),
+            Ok(("", expected()))
+        );
+        assert_eq!(
+            parse_instruction(&AssemblerSyntax::default(), "add $0, $1, $2"),
+            Ok(("", expected()))
+        );
+        assert_eq!(
+            parse_instruction(&AssemblerSyntax::default(), "add $0,$1 ,$2"),
+            Ok(("", expected()))
+        );
+
+        let mut modulo = expected();
+        modulo.opcode = Some(Token::Opcode(Opcode::MOD));
+        assert_eq!(
+            parse_instruction(&AssemblerSyntax::default(), "mod $0 $1 $2"),
+            Ok(("", modulo))
+        );
+    }
+
+    #[test]
+    fn test_parse_single_register_instruction() {
+        for &(source, opcode) in &[("push $3", Opcode::PUSH), ("pop $4", Opcode::POP)] {
+            let (_, instruction) = parse_instruction(&AssemblerSyntax::default(), source).unwrap();
+            assert_eq!(instruction.get_opcode(), Some(opcode));
+            assert!(instruction.operand1.is_some());
+            assert_eq!(instruction.operand2, None);
+        }
+    }
+
     #[test]
     fn test_parse_string() {
         assert_eq!(
@@ -227,12 +545,17 @@ mod tests {
             parse_string(r#""\tabc\n""#),
             Ok(("", Token::StringOperand(r#"\tabc\n"#.to_string())))
         );
+        assert_eq!(
+            parse_string(r#"'it\'s "quoted"'"#),
+            Ok(("", Token::StringOperand(r#"it\'s "quoted""#.to_string())))
+        );
+        assert!(parse_string("'abc\"").is_err());
     }
 
     #[test]
     fn test_parse_label_declaration() {
         assert_eq!(
-            parse_label_declaration("label1: "),
+            parse_label_declaration(&AssemblerSyntax::default(), "label1: "),
             Ok(("", Token::LabelDeclaration("label1".to_string())))
         );
     }
@@ -255,8 +578,11 @@ mod tests {
 
     #[test]
     fn test_parse_string_directive() {
-        let result = parse_directive("test1: .asciiz \"Hello, World!\"");
-        assert_eq!(result.is_ok(), true);
+        let result = parse_directive(
+            &AssemblerSyntax::default(),
+            "test1: .asciiz \"Hello, World!\"",
+        );
+        assert!(result.is_ok());
 
         let (_, directive) = result.unwrap();
 
@@ -274,12 +600,81 @@ mod tests {
     #[test]
     fn test_program_with_directive() {
         let prog = ".data\nhello: .asciiz \"Howdy!\"\n.code\nhlt";
-        assert_eq!(parse_program(prog).is_ok(), true);
+        assert!(parse_program(&AssemblerSyntax::default(), prog).is_ok());
+    }
+
+    #[test]
+    fn test_parse_program_with_source() {
+        let result = parse_program_with_source(
+            &AssemblerSyntax::default(),
+            "load $0 #100\n  loop: add $0 $1 $2\nhlt",
+        );
+        let (remaining_input, (program, source)) = result.unwrap();
+        assert_eq!("", remaining_input);
+        assert_eq!(program.instructions.len(), 3);
+        assert_eq!(source, vec!["load $0 #100", "loop: add $0 $1 $2", "hlt"]);
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let (remaining, (program, source)) = parse_program_with_source(
+            &AssemblerSyntax::default(),
+            "; setup\n  ; more setup\nload $0 #10 ; counter\n\n; done\nhlt ; stop",
+        )
+        .unwrap();
+        assert_eq!("", remaining);
+        assert_eq!(program.instructions.len(), 2);
+        assert_eq!(source, vec!["load $0 #10", "hlt"]);
+
+        let (remaining, program) = parse_program(
+            &AssemblerSyntax::default(),
+            "; nothing\n\n  ; to see here\n",
+        )
+        .unwrap();
+        assert_eq!("", remaining);
+        assert!(program.instructions.is_empty());
+
+        // Anything else still needs at least one instruction.
+        assert!(parse_program(&AssemblerSyntax::default(), "$0 ; register").is_err());
+    }
+
+    #[test]
+    fn test_parse_program_with_syntax() {
+        let default = "; count to ten\nstart: load $0 #10 ; counter\njmp @start";
+        let dialect = AssemblerSyntax {
+            comment_prefix: "//".to_string(),
+            label_suffix: "".to_string(),
+            immediate_prefix: "%".to_string(),
+        };
+        let other = "// count to ten\nstart load $0 %10 // counter\njmp @start";
+
+        let (remaining, expected) = parse_program(&AssemblerSyntax::default(), default).unwrap();
+        assert_eq!("", remaining);
+        assert_eq!(expected.instructions.len(), 2);
+        assert_eq!(
+            expected.instructions[0],
+            AssemblyInstruction {
+                opcode: Some(Token::Opcode(Opcode::LOAD)),
+                label: Some(Token::LabelDeclaration("start".to_string())),
+                operand1: Some(Token::Register(0)),
+                operand2: Some(Token::IntegerOperand(10)),
+                ..Default::default()
+            }
+        );
+
+        let (remaining, program) = parse_program(&dialect, other).unwrap();
+        assert_eq!("", remaining);
+        assert_eq!(program.instructions, expected.instructions);
+
+        // Mnemonics aren't mistaken for labels when there is no suffix.
+        let (_, program) = parse_program(&dialect, "hlt").unwrap();
+        assert_eq!(program.instructions[0].label, None);
     }
 
     #[test]
     fn test_parse_program() {
         let result = parse_program(
+            &AssemblerSyntax::default(),
             r##"max: .something #99
                 load $0 #100
                 label1: load $1 #200
@@ -290,7 +685,7 @@ mod tests {
                 "##,
         );
 
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
 
         let (remaining_input, program) = result.unwrap();
 
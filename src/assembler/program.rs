@@ -18,11 +18,56 @@ impl Program {
     }
     result
   }
+
+  /// Stable byte representation of the program that is suitable for keying
+  /// a cache of compiled programs. Labels are resolved so only the emitted
+  /// code matters, not the spelling of the source. Directives don't have an
+  /// encoding of their own yet, so they're represented by a normalized text
+  /// form instead.
+  pub fn canonical_bytes(&self, st: &SymbolTable) -> Vec<u8> {
+    let mut result = vec![];
+    for inst in &self.instructions {
+      if inst.has_opcode() {
+        result.append(&mut inst.to_bytes(st));
+        continue;
+      }
+
+      let mut text = format!(".{}", inst.get_directive().unwrap_or_default());
+      for operand in [&inst.operand1, &inst.operand2, &inst.operand3].iter() {
+        match operand {
+          Some(Token::IntegerOperand(v)) => text.push_str(&format!(" {}", v)),
+          Some(Token::Register(r)) => text.push_str(&format!(" ${}", r)),
+          Some(Token::StringOperand(s)) => text.push_str(&format!(" {:?}", s)),
+          Some(Token::LabelUsage(label)) => {
+            let offset = st.get(label).map_or(0, |info| info.offset());
+            text.push_str(&format!(" {}", offset));
+          }
+          _ => (),
+        }
+      }
+      text.push('\n');
+      result.extend_from_slice(text.as_bytes());
+    }
+    result
+  }
+
+  /// Content hash of the canonical bytes. It uses 64-bit FNV-1a so that the
+  /// hash doesn't change between runs or Rust versions.
+  pub fn canonical_hash(&self, st: &SymbolTable) -> u64 {
+    self
+      .canonical_bytes(st)
+      .iter()
+      .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+      })
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::assembler::parsers::{parse_program, AssemblerSyntax};
+  use crate::assembler::Assembler;
   #[test]
   fn test_program_to_bytes() {
     let st = SymbolTable::new();
@@ -47,4 +92,26 @@ mod tests {
     let program_bytes: Vec<u8> = vec![load_opcode, 0, 0, 100, load_opcode, 1, 0, 200];
     assert_eq!(program.to_bytes(&st), program_bytes);
   }
+
+  #[test]
+  fn test_canonical_bytes() {
+    let syntax = AssemblerSyntax::default();
+    let canonical = |source| {
+      let mut assembler = Assembler::new();
+      let st = assembler.resolve_symbols(source).unwrap();
+      let (_, program) = parse_program(&syntax, source).unwrap();
+      (program.canonical_bytes(&st), program.canonical_hash(&st))
+    };
+
+    let (bytes, hash) = canonical("start: load $0 #100\njmp @start\n.data\n.word #7");
+    let (other_bytes, other_hash) =
+      canonical("; same program\nbegin: LOAD $0, #(50*2)\n  jmp @begin\n.data\n.word #(3+4)");
+    assert_eq!(bytes, other_bytes);
+    assert_eq!(hash, other_hash);
+
+    let (different_bytes, different_hash) =
+      canonical("start: load $0 #100\njmp @start\n.data\n.word #8");
+    assert_ne!(bytes, different_bytes);
+    assert_ne!(hash, different_hash);
+  }
 }
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SymbolType {
     Label,
     Integer,
@@ -20,6 +20,52 @@ impl SymbolInfo {
             symbol_type: t,
         }
     }
+
+    /// Offset of the symbol. Labels are absolute addresses in the executable
+    /// while data symbols are offsets into the data section.
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn symbol_type(&self) -> SymbolType {
+        self.symbol_type
+    }
 }
 
 pub type SymbolTable = HashMap<String, SymbolInfo>;
+
+/// Formats the labels of the symbol table as a symbol map that can be shipped
+/// next to a stripped executable. Each line holds the hex address followed by
+/// the name i.e. `00000040 start`, sorted by address. Data symbols aren't
+/// included as they don't refer to addresses in the executable.
+pub fn format_symbol_map(st: &SymbolTable) -> String {
+    let mut labels: Vec<(u32, &str)> = st
+        .iter()
+        .filter(|(_, info)| info.symbol_type() == SymbolType::Label)
+        .map(|(name, info)| (info.offset(), name.as_str()))
+        .collect();
+    labels.sort_unstable();
+
+    let mut map = String::new();
+    for (offset, name) in labels {
+        map.push_str(&format!("{:08x} {}\n", offset, name));
+    }
+    map
+}
+
+/// Parses a symbol map produced by format_symbol_map into a table of names
+/// and addresses. Returns the number of the first malformed line on error.
+pub fn parse_symbol_map(map: &str) -> Result<HashMap<String, usize>, usize> {
+    let mut symbols = HashMap::new();
+    for (n, line) in map.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+        let (offset, name) = match (fields.next(), fields.next(), fields.next()) {
+            (None, _, _) => continue,
+            (Some(offset), Some(name), None) => (offset, name),
+            _ => return Err(n + 1),
+        };
+        let offset = usize::from_str_radix(offset, 16).map_err(|_| n + 1)?;
+        symbols.insert(name.to_string(), offset);
+    }
+    Ok(symbols)
+}
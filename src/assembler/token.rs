@@ -21,6 +21,8 @@ impl Token {
             Token::Register(reg) => {
                 return vec![*reg];
             }
+            // Negative values end up in two's complement. The assembler makes
+            // sure that the value fits in 16 bits before we get here.
             Token::IntegerOperand(v) => {
                 return (*v as u16).to_be_bytes().to_vec();
             }
@@ -55,6 +57,8 @@ mod tests {
 
         // We use big-endian format to store integers.
         assert_eq!(Token::IntegerOperand(0xFFEE).to_bytes(), vec![0xFF, 0xEE]);
+        assert_eq!(Token::IntegerOperand(-5).to_bytes(), vec![0xFF, 0xFB]);
+        assert_eq!(Token::IntegerOperand(-32768).to_bytes(), vec![0x80, 0x00]);
 
         assert_eq!(
             Token::StringOperand(String::from("AZAD")).to_bytes(),
@@ -0,0 +1,304 @@
+/// This module contains a simple disassembler that turns Iridium bytecode
+/// back into human readable assembly.
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::assembler::BIN_HEADER_LENGTH;
+use crate::opcode::Opcode;
+
+/// Kinds of operands that an instruction can encode after its opcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandKind {
+    /// 8-bit register number.
+    Register,
+
+    /// 16-bit big-endian immediate value.
+    Immediate,
+
+    /// 32-bit big-endian immediate value.
+    WideImmediate,
+}
+
+/// Returns the operands encoded by the specified opcode. Remaining bytes of
+/// the instruction are padding.
+pub fn operand_kinds(opcode: Opcode) -> &'static [OperandKind] {
+    use OperandKind::*;
+
+    match opcode {
+        Opcode::LOAD | Opcode::SHLI | Opcode::SHRI => &[Register, Immediate],
+        Opcode::SYS
+        | Opcode::JMPI
+        | Opcode::JMPFI
+        | Opcode::JMPBI
+        | Opcode::JEQI
+        | Opcode::JNEQI
+        | Opcode::JOI
+        | Opcode::JNOI
+        | Opcode::CALLI => &[Immediate],
+        Opcode::LOADW => &[Register, WideImmediate],
+        Opcode::ADD
+        | Opcode::SUB
+        | Opcode::MUL
+        | Opcode::DIV
+        | Opcode::DIVMOD
+        | Opcode::AMOADD
+        | Opcode::ABSDIFF
+        | Opcode::MOD
+        | Opcode::SHL
+        | Opcode::SHR
+        | Opcode::XORRANGE
+        | Opcode::LMW
+        | Opcode::SMW
+        | Opcode::RMOV
+        | Opcode::SELECT => &[Register, Register, Register],
+        Opcode::EQ
+        | Opcode::NEQ
+        | Opcode::GT
+        | Opcode::GTE
+        | Opcode::LT
+        | Opcode::LTE
+        | Opcode::BSWAP
+        | Opcode::DBNZ
+        | Opcode::LPROG
+        | Opcode::SPROG
+        | Opcode::BZERO
+        | Opcode::JNEG
+        | Opcode::JPOS
+        | Opcode::INARG
+        | Opcode::XCHG
+        | Opcode::LOADM
+        | Opcode::STOREM
+        | Opcode::NEG
+        | Opcode::ABS
+        | Opcode::MOV => &[Register, Register],
+        Opcode::JMP
+        | Opcode::JMPF
+        | Opcode::JMPB
+        | Opcode::JEQ
+        | Opcode::JNEQ
+        | Opcode::JO
+        | Opcode::JNO
+        | Opcode::ALOC
+        | Opcode::INC
+        | Opcode::DEC
+        | Opcode::CALL
+        | Opcode::CLOCK
+        | Opcode::FLAGLOG
+        | Opcode::SETZ
+        | Opcode::SETNZ
+        | Opcode::SP
+        | Opcode::PUSH
+        | Opcode::POP => &[Register],
+        Opcode::HLT | Opcode::HALTR | Opcode::RET | Opcode::NOP | Opcode::IGL => &[],
+    }
+}
+
+/// Operand decoded from an instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Register(u8),
+    Immediate(i32),
+}
+
+/// Instruction decoded from bytecode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedInstruction {
+    pub opcode: Opcode,
+    pub operands: Vec<Operand>,
+
+    /// Number of bytes taken by the instruction including padding.
+    pub size: usize,
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.opcode)?;
+        for operand in &self.operands {
+            match operand {
+                Operand::Register(reg) => write!(f, " ${}", reg)?,
+                Operand::Immediate(value) => write!(f, " #{}", value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the instruction at the specified offset of the bytes. Returns None
+/// if the bytes end before the instruction does. Unknown opcodes decode to
+/// IGL. It never panics regardless of the input so its safe to fuzz.
+pub fn decode_instruction(bytes: &[u8], offset: usize) -> Option<DecodedInstruction> {
+    let opcode = Opcode::from(*bytes.get(offset)?);
+    let size = opcode.instruction_size();
+    let inst = bytes.get(offset..offset.checked_add(size)?)?;
+    let byte = |i: usize| inst.get(i).copied();
+
+    let mut operands = vec![];
+    let mut i = 1;
+    for kind in operand_kinds(opcode) {
+        match kind {
+            OperandKind::Register => {
+                operands.push(Operand::Register(byte(i)?));
+                i += 1;
+            }
+            OperandKind::Immediate => {
+                let value = u16::from_be_bytes([byte(i)?, byte(i + 1)?]);
+                operands.push(Operand::Immediate(i32::from(value)));
+                i += 2;
+            }
+            OperandKind::WideImmediate => {
+                let bytes = [byte(i)?, byte(i + 1)?, byte(i + 2)?, byte(i + 3)?];
+                operands.push(Operand::Immediate(i32::from_be_bytes(bytes)));
+                i += 4;
+            }
+        }
+    }
+
+    Some(DecodedInstruction {
+        opcode,
+        operands,
+        size,
+    })
+}
+
+/// Disassembles a single instruction. Missing bytes are treated as zero.
+pub fn disassemble_instruction(bytes: &[u8]) -> String {
+    let opcode = Opcode::from(bytes.first().copied().unwrap_or(0));
+    let mut padded = vec![0; opcode.instruction_size()];
+    let len = bytes.len().min(padded.len());
+    padded[..len].copy_from_slice(&bytes[..len]);
+
+    decode_instruction(&padded, 0)
+        .expect("bytes are padded to the instruction size")
+        .to_string()
+}
+
+/// Disassembles the bytecode (without the executable header) into one
+/// instruction per line. If `show_bytes` is set, each line is prefixed with
+/// the hex of the instruction's raw bytes.
+pub fn disassemble(bytecode: &[u8], show_bytes: bool) -> String {
+    disassemble_with_symbols(bytecode, show_bytes, &HashMap::new())
+}
+
+/// Same as disassemble but also emits a label line for every instruction
+/// whose address is found in the symbols. Addresses include the executable
+/// header just like the ones recorded by the assembler.
+pub fn disassemble_with_symbols(
+    bytecode: &[u8],
+    show_bytes: bool,
+    symbols: &HashMap<String, usize>,
+) -> String {
+    let mut labels: Vec<(usize, &str)> = symbols
+        .iter()
+        .map(|(name, &offset)| (offset, name.as_str()))
+        .collect();
+    labels.sort_unstable();
+
+    let mut result = String::new();
+    let mut offset = 0;
+    while offset < bytecode.len() {
+        let addr = BIN_HEADER_LENGTH + offset;
+        for (_, name) in labels.iter().filter(|(label, _)| *label == addr) {
+            result.push_str(&format!("{}:\n", name));
+        }
+
+        let size = Opcode::from(bytecode[offset]).instruction_size();
+        let inst = &bytecode[offset..(offset + size).min(bytecode.len())];
+        offset += size;
+
+        if show_bytes {
+            let hex: Vec<String> = inst.iter().map(|b| format!("{:02x}", b)).collect();
+            result.push_str(&format!("{:<26}", hex.join(" ")));
+        }
+        result.push_str(&disassemble_instruction(inst));
+        result.push('\n');
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_instruction() {
+        assert_eq!(disassemble_instruction(&[1, 0, 1, 244]), "LOAD $0 #500");
+        assert_eq!(disassemble_instruction(&[2, 0, 1, 2]), "ADD $0 $1 $2");
+        assert_eq!(disassemble_instruction(&[0, 0, 0, 0]), "HLT");
+        assert_eq!(
+            disassemble_instruction(&[29, 1, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0]),
+            "LOADW $1 #-1"
+        );
+    }
+
+    #[test]
+    fn test_decode_instruction() {
+        let bytes = [1, 3, 1, 244, 29, 1, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0];
+        assert_eq!(
+            decode_instruction(&bytes, 0),
+            Some(DecodedInstruction {
+                opcode: Opcode::LOAD,
+                operands: vec![Operand::Register(3), Operand::Immediate(500)],
+                size: 4,
+            })
+        );
+        assert_eq!(
+            decode_instruction(&bytes, 4),
+            Some(DecodedInstruction {
+                opcode: Opcode::LOADW,
+                operands: vec![Operand::Register(1), Operand::Immediate(-1)],
+                size: 8,
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_truncated_instruction() {
+        assert_eq!(decode_instruction(&[], 0), None);
+        assert_eq!(decode_instruction(&[1, 0, 1], 0), None);
+        assert_eq!(decode_instruction(&[29, 1, 0, 0, 0, 0, 0], 0), None);
+        assert_eq!(decode_instruction(&[0, 0, 0, 0], 4), None);
+        assert_eq!(decode_instruction(&[0, 0, 0, 0], usize::MAX), None);
+    }
+
+    #[test]
+    fn test_decode_random_bytes() {
+        // Simple LCG so the test is deterministic.
+        let mut seed: u32 = 0x1234_5678;
+        let mut next = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8
+        };
+
+        for len in 0..64 {
+            let bytes: Vec<u8> = (0..len).map(|_| next()).collect();
+            for offset in 0..=len {
+                if let Some(inst) = decode_instruction(&bytes, offset) {
+                    assert!(offset + inst.size <= bytes.len());
+                    if Opcode::from(bytes[offset]) == Opcode::IGL {
+                        assert_eq!(inst.opcode, Opcode::IGL);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_disassemble_wide() {
+        let bytecode = [29, 1, 0, 1, 0x86, 0xA0, 0xFF, 0xFF, 0, 0, 0, 0];
+        assert_eq!(disassemble(&bytecode, false), "LOADW $1 #100000\nHLT\n");
+        assert_eq!(
+            disassemble(&bytecode, true),
+            "1d 01 00 01 86 a0 ff ff   LOADW $1 #100000\n00 00 00 00               HLT\n"
+        );
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let bytecode = [1, 0, 0, 10, 8, 1, 0, 0];
+        assert_eq!(disassemble(&bytecode, false), "LOAD $0 #10\nJMPB $1\n");
+        assert_eq!(
+            disassemble(&bytecode, true),
+            "01 00 00 0a               LOAD $0 #10\n08 01 00 00               JMPB $1\n"
+        );
+    }
+}
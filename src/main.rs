@@ -6,10 +6,14 @@ extern crate log;
 extern crate env_logger;
 
 pub mod assembler;
+pub mod disassembler;
 pub mod opcode;
 pub mod repl;
 pub mod vm;
 
+#[cfg(test)]
+mod test_support;
+
 use repl::REPL;
 use structopt::StructOpt;
 
@@ -1,3 +1,7 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
 /// Opcode enum represents the opcodes for all the instructions supported by the VM.
 /// Each opcode is represented by a u8 in the instruction format.
 #[derive(FromPrimitive, Copy, Clone, Debug, PartialEq)]
@@ -5,7 +9,9 @@ pub enum Opcode {
     // Halt instruction.
     HLT = 0,
 
-    // Load a value into register.
+    // Load a value into register: LOAD $0 #500. The value is 16 bits and
+    // zero-extended. The assembler encodes a LOAD of a value that doesn't
+    // fit as LOADW.
     LOAD = 1,
 
     // Add operation. It operates on registers.
@@ -69,20 +75,213 @@ pub enum Opcode {
     // Decrement by 1. DEC $0
     DEC = 19,
 
+    // Byte swap: BSWAP $0 $1 where $0 = $1 with its four bytes reversed.
+    BSWAP = 20,
+
+    // Call a subroutine: CALL $0. It pushes the return address on the call stack
+    // and performs an absolute jump to the value of the register.
+    CALL = 21,
+
+    // Return from a subroutine: RET. It pops the return address off the call stack.
+    RET = 22,
+
+    // Set If Equal: SETZ $0. Sets $0 to 1 if equal_flag is true and 0 otherwise.
+    SETZ = 23,
+
+    // Set If Not Equal: SETNZ $0. Sets $0 to 1 if equal_flag is false and 0 otherwise.
+    SETNZ = 24,
+
+    // Stack Pointer: SP $0. Writes the current depth of the operand stack into $0.
+    SP = 25,
+
+    // Load Program Byte: LPROG $0 $1 where $0 = program[$1].
+    LPROG = 26,
+
+    // Store Program Byte: SPROG $0 $1 where program[$1] = low byte of $0. This is
+    // self-modifying code so any cached decoding of the patched instruction is stale.
+    SPROG = 27,
+
+    // Halt With Result: HALTR. Halts like HLT and marks the registers as the result
+    // of the program. The result is available through VM::result_registers().
+    HALTR = 28,
+
+    // Load Wide: LOADW $0 #100000. Loads a full 32-bit immediate into the register.
+    // Unlike other instructions, it's 8 bytes long: opcode, register, the
+    // big-endian value and 2 bytes of padding.
+    LOADW = 29,
+
+    // Register Move: RMOV $0 $1 $2 copies $2 consecutive registers starting at $1 to
+    // the ones starting at $0. Overlapping ranges are handled like memmove.
+    RMOV = 30,
+
+    // System Call: SYS #1 invokes the syscall handler registered under the number.
+    SYS = 31,
+
+    // Jump If Overflow: JO $0. It performs an absolute jump to the value of the register
+    // if the last arithmetic operation overflowed.
+    JO = 32,
+
+    // Jump If No Overflow: JNO $0. It performs an absolute jump to the value of the register
+    // if the last arithmetic operation didn't overflow.
+    JNO = 33,
+
+    // Decrement And Branch If Not Zero: DBNZ $0 $1. It decrements $0 and jumps to the
+    // value of $1 unless $0 became zero.
+    DBNZ = 34,
+
+    // Clock: CLOCK $0. Stores the milliseconds elapsed since the VM was created in the register.
+    // The value depends on wall clock time so programs using it aren't deterministic.
+    CLOCK = 35,
+
+    // Select: SELECT $0 $1 $2 where $0 = $1 if equal_flag is set, otherwise $0 = $2.
+    SELECT = 36,
+
+    // Divide With Remainder: DIVMOD $0 $1 $2 where $2 = $0 / $1 and the remainder goes to the
+    // register right after $2 i.e. $3.
+    DIVMOD = 37,
+
+    // Shift Left Immediate: SHLI $0 #4 shifts the register left by the immediate amount.
+    // The amount is masked to 0..31.
+    SHLI = 38,
+
+    // Shift Right Immediate: SHRI $0 #4 shifts the register right by the immediate amount,
+    // preserving its sign. The amount is masked to 0..31.
+    SHRI = 39,
+
+    // Atomic Add: AMOADD $0 $1 $2 atomically adds $2 to the 32-bit big-endian word of the
+    // shared heap at address $1 and stores the previous value of the word in $0.
+    AMOADD = 40,
+
+    // Zero Memory: BZERO $0 $1 clears $1 bytes of the heap starting at address $0.
+    BZERO = 41,
+
+    // Jump If Negative: JNEG $0 $1 jumps to the address in $1 if $0 is negative.
+    JNEG = 42,
+
+    // Jump If Positive: JPOS $0 $1 jumps to the address in $1 if $0 is positive (non-zero).
+    JPOS = 43,
+
+    // Absolute Difference: ABSDIFF $0 $1 $2 where $2 = |$0 - $1|. The subtraction and the absolute
+    // value wrap around on overflow and set the overflow flag.
+    ABSDIFF = 44,
+
+    // Input Argument: INARG $0 $1 loads the $1-th 32-bit big-endian element of the input
+    // buffer into $0.
+    INARG = 45,
+
+    // Log Equal Flag: FLAGLOG $0 shifts $0 left by one bit and stores the equal flag in its
+    // lowest bit, building a history of comparison outcomes.
+    FLAGLOG = 46,
+
+    // XOR Range: XORRANGE $0 $1 $2 XORs together $2 bytes of the heap starting at address $1
+    // and stores the result in $0.
+    XORRANGE = 47,
+
+    // Atomic Exchange: XCHG $0 $1 atomically swaps $0 with the 32-bit big-endian word of the
+    // shared heap at address $1.
+    XCHG = 48,
+
+    // Relative Jump Forward: JMPFI #8 jumps forward by the immediate number of bytes counted
+    // from the start of the instruction. The assembler emits it for position independent code.
+    JMPFI = 49,
+
+    // Relative Jump Backward: JMPBI #8 jumps backward by the immediate number of bytes counted
+    // from the start of the instruction.
+    JMPBI = 50,
+
+    // Push: PUSH $0 pushes the value of $0 onto the operand stack.
+    PUSH = 51,
+
+    // Pop: POP $0 pops the top of the operand stack into $0.
+    POP = 52,
+
+    // Load Multiple Words: LMW $0 $1 $2 loads $2 consecutive 32-bit big-endian words of the heap
+    // starting at address $1 into the registers starting at $0.
+    LMW = 53,
+
+    // Store Multiple Words: SMW $0 $1 $2 stores $2 consecutive registers starting at $0 as 32-bit
+    // big-endian words into the heap starting at address $1.
+    SMW = 54,
+
+    // Jump Immediate: JMPI 0x0040 jumps to the absolute address in the 16-bit operand. The
+    // assembler emits it for jumps to a label e.g. `jmp @loop`.
+    JMPI = 55,
+
+    // Modulo: MOD $0 $1 $2 stores the remainder of $0 / $1 in $2. The remainder has the sign
+    // of the dividend.
+    MOD = 56,
+
+    // Shift Left: SHL $0 $1 $2 shifts $1 left by the amount in $2 and stores the result in $0.
+    // Only the low 5 bits of the amount are used.
+    SHL = 57,
+
+    // Shift Right: SHR $0 $1 $2 shifts $1 right by the amount in $2, preserving the sign, and
+    // stores the result in $0. Only the low 5 bits of the amount are used.
+    SHR = 58,
+
+    // Call Immediate: CALLI 0x0040 calls the subroutine at the absolute address in the 16-bit
+    // operand. The assembler emits it for calls to a label e.g. `call @square`.
+    CALLI = 59,
+
+    // Load Memory: LOADM $0 $1 loads the 32-bit big-endian word at heap address $1 into $0.
+    LOADM = 60,
+
+    // Store Memory: STOREM $0 $1 stores $1 as a 32-bit big-endian word at heap address $0.
+    STOREM = 61,
+
+    // Negate: NEG $0 $1 where $0 = -$1. Negating i32::MIN saturates to i32::MAX
+    // and sets the overflow flag.
+    NEG = 62,
+
+    // Absolute value: ABS $0 $1 where $0 = |$1|. Like NEG, it saturates to i32::MAX
+    // for i32::MIN and sets the overflow flag.
+    ABS = 63,
+
+    // Move: MOV $0 $1 copies $1 into $0.
+    MOV = 64,
+
+    // Jump If Equal Immediate: JEQI 0x0040 jumps to the absolute address in the 16-bit operand if
+    // the equal flag is set. The assembler emits it for `jeq @label`.
+    JEQI = 65,
+
+    // Jump If Not Equal Immediate: like JEQI but jumps if the equal flag is clear.
+    JNEQI = 66,
+
+    // Jump If Overflow Immediate: like JEQI but jumps if the overflow flag is set.
+    JOI = 67,
+
+    // Jump If No Overflow Immediate: like JEQI but jumps if the overflow flag is clear.
+    JNOI = 68,
+
+    // No Operation: NOP does nothing. The assembler pads code aligned by `.align` with it.
+    NOP = 69,
+
     // Illegal instruction.
     IGL = 255,
 }
 
 /// Instruction struct represents an instruction for the VM. We support the following
-/// instruction formats. All these instructions are 4 bytes.
+/// instruction formats. All these instructions are 4 bytes except the last one
+/// which is 8 bytes.
 ///
 /// 1. opcode: 8bits
 /// 2. opcode: 8bits, register: 8bits
 /// 3. opcode: 8bits register: 8bits: operand1: 8bits
 /// 4. opcode: 8bits register: 8bits: operand1: 8bits: operand2: 8bits
 /// 5. opcode: 8bits register: 8bits: operand1: 16bits
+/// 6. opcode: 8bits register: 8bits: operand1: 32bits: padding: 16bits
 ///
 
+impl Opcode {
+    /// Number of bytes taken by an instruction with this opcode.
+    pub fn instruction_size(self) -> usize {
+        match self {
+            Opcode::LOADW => 8,
+            _ => 4,
+        }
+    }
+}
+
 impl From<Opcode> for u8 {
     fn from(opcode: Opcode) -> Self {
         opcode as u8
@@ -98,9 +297,24 @@ impl From<u8> for Opcode {
     }
 }
 
-impl From<&str> for Opcode {
-    fn from(v: &str) -> Self {
-        match v.to_uppercase().as_str() {
+/// Error returned when a string isn't the mnemonic of any opcode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownMnemonic(pub String);
+
+impl fmt::Display for UnknownMnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown mnemonic {}", self.0)
+    }
+}
+
+impl Error for UnknownMnemonic {}
+
+/// Looks up the opcode for a mnemonic, ignoring case.
+impl TryFrom<&str> for Opcode {
+    type Error = UnknownMnemonic;
+
+    fn try_from(v: &str) -> Result<Self, Self::Error> {
+        let opcode = match v.to_uppercase().as_str() {
             "HLT" => Opcode::HLT,
             "LOAD" => Opcode::LOAD,
             "ADD" => Opcode::ADD,
@@ -121,8 +335,60 @@ impl From<&str> for Opcode {
             "ALOC" => Opcode::ALOC,
             "INC" => Opcode::INC,
             "DEC" => Opcode::DEC,
-            _ => Opcode::IGL,
-        }
+            "BSWAP" => Opcode::BSWAP,
+            "CALL" => Opcode::CALL,
+            "RET" => Opcode::RET,
+            "SETZ" => Opcode::SETZ,
+            "SETNZ" => Opcode::SETNZ,
+            "SP" => Opcode::SP,
+            "LPROG" => Opcode::LPROG,
+            "SPROG" => Opcode::SPROG,
+            "HALTR" => Opcode::HALTR,
+            "LOADW" => Opcode::LOADW,
+            "RMOV" => Opcode::RMOV,
+            "SYS" => Opcode::SYS,
+            "JO" => Opcode::JO,
+            "JNO" => Opcode::JNO,
+            "DBNZ" => Opcode::DBNZ,
+            "CLOCK" => Opcode::CLOCK,
+            "SELECT" => Opcode::SELECT,
+            "DIVMOD" => Opcode::DIVMOD,
+            "SHLI" => Opcode::SHLI,
+            "SHRI" => Opcode::SHRI,
+            "AMOADD" => Opcode::AMOADD,
+            "BZERO" => Opcode::BZERO,
+            "JNEG" => Opcode::JNEG,
+            "JPOS" => Opcode::JPOS,
+            "ABSDIFF" => Opcode::ABSDIFF,
+            "INARG" => Opcode::INARG,
+            "FLAGLOG" => Opcode::FLAGLOG,
+            "XORRANGE" => Opcode::XORRANGE,
+            "XCHG" => Opcode::XCHG,
+            "JMPFI" => Opcode::JMPFI,
+            "JMPBI" => Opcode::JMPBI,
+            "PUSH" => Opcode::PUSH,
+            "POP" => Opcode::POP,
+            "LMW" => Opcode::LMW,
+            "SMW" => Opcode::SMW,
+            "JMPI" => Opcode::JMPI,
+            "MOD" => Opcode::MOD,
+            "SHL" => Opcode::SHL,
+            "SHR" => Opcode::SHR,
+            "CALLI" => Opcode::CALLI,
+            "LOADM" => Opcode::LOADM,
+            "STOREM" => Opcode::STOREM,
+            "NEG" => Opcode::NEG,
+            "ABS" => Opcode::ABS,
+            "MOV" => Opcode::MOV,
+            "JEQI" => Opcode::JEQI,
+            "JNEQI" => Opcode::JNEQI,
+            "JOI" => Opcode::JOI,
+            "JNOI" => Opcode::JNOI,
+            "NOP" => Opcode::NOP,
+            "IGL" => Opcode::IGL,
+            _ => return Err(UnknownMnemonic(v.to_string())),
+        };
+        Ok(opcode)
     }
 }
 
@@ -164,6 +430,56 @@ mod tests {
         assert_eq!(Opcode::ALOC, Opcode::from(17));
         assert_eq!(Opcode::INC, Opcode::from(18));
         assert_eq!(Opcode::DEC, Opcode::from(19));
+        assert_eq!(Opcode::BSWAP, Opcode::from(20));
+        assert_eq!(Opcode::CALL, Opcode::from(21));
+        assert_eq!(Opcode::RET, Opcode::from(22));
+        assert_eq!(Opcode::SETZ, Opcode::from(23));
+        assert_eq!(Opcode::SETNZ, Opcode::from(24));
+        assert_eq!(Opcode::SP, Opcode::from(25));
+        assert_eq!(Opcode::LPROG, Opcode::from(26));
+        assert_eq!(Opcode::SPROG, Opcode::from(27));
+        assert_eq!(Opcode::HALTR, Opcode::from(28));
+        assert_eq!(Opcode::LOADW, Opcode::from(29));
+        assert_eq!(Opcode::RMOV, Opcode::from(30));
+        assert_eq!(Opcode::SYS, Opcode::from(31));
+        assert_eq!(Opcode::JO, Opcode::from(32));
+        assert_eq!(Opcode::JNO, Opcode::from(33));
+        assert_eq!(Opcode::DBNZ, Opcode::from(34));
+        assert_eq!(Opcode::CLOCK, Opcode::from(35));
+        assert_eq!(Opcode::SELECT, Opcode::from(36));
+        assert_eq!(Opcode::DIVMOD, Opcode::from(37));
+        assert_eq!(Opcode::SHLI, Opcode::from(38));
+        assert_eq!(Opcode::SHRI, Opcode::from(39));
+        assert_eq!(Opcode::AMOADD, Opcode::from(40));
+        assert_eq!(Opcode::BZERO, Opcode::from(41));
+        assert_eq!(Opcode::JNEG, Opcode::from(42));
+        assert_eq!(Opcode::JPOS, Opcode::from(43));
+        assert_eq!(Opcode::ABSDIFF, Opcode::from(44));
+        assert_eq!(Opcode::INARG, Opcode::from(45));
+        assert_eq!(Opcode::FLAGLOG, Opcode::from(46));
+        assert_eq!(Opcode::XORRANGE, Opcode::from(47));
+        assert_eq!(Opcode::XCHG, Opcode::from(48));
+        assert_eq!(Opcode::JMPFI, Opcode::from(49));
+        assert_eq!(Opcode::JMPBI, Opcode::from(50));
+        assert_eq!(Opcode::PUSH, Opcode::from(51));
+        assert_eq!(Opcode::POP, Opcode::from(52));
+        assert_eq!(Opcode::LMW, Opcode::from(53));
+        assert_eq!(Opcode::SMW, Opcode::from(54));
+        assert_eq!(Opcode::JMPI, Opcode::from(55));
+        assert_eq!(Opcode::MOD, Opcode::from(56));
+        assert_eq!(Opcode::SHL, Opcode::from(57));
+        assert_eq!(Opcode::SHR, Opcode::from(58));
+        assert_eq!(Opcode::CALLI, Opcode::from(59));
+        assert_eq!(Opcode::LOADM, Opcode::from(60));
+        assert_eq!(Opcode::STOREM, Opcode::from(61));
+        assert_eq!(Opcode::NEG, Opcode::from(62));
+        assert_eq!(Opcode::ABS, Opcode::from(63));
+        assert_eq!(Opcode::MOV, Opcode::from(64));
+        assert_eq!(Opcode::JEQI, Opcode::from(65));
+        assert_eq!(Opcode::JNEQI, Opcode::from(66));
+        assert_eq!(Opcode::JOI, Opcode::from(67));
+        assert_eq!(Opcode::JNOI, Opcode::from(68));
+        assert_eq!(Opcode::NOP, Opcode::from(69));
     }
 
     #[test]
@@ -188,31 +504,153 @@ mod tests {
         assert_eq!(Opcode::ALOC as u8, 17);
         assert_eq!(Opcode::INC as u8, 18);
         assert_eq!(Opcode::DEC as u8, 19);
+        assert_eq!(Opcode::BSWAP as u8, 20);
+        assert_eq!(Opcode::CALL as u8, 21);
+        assert_eq!(Opcode::RET as u8, 22);
+        assert_eq!(Opcode::SETZ as u8, 23);
+        assert_eq!(Opcode::SETNZ as u8, 24);
+        assert_eq!(Opcode::SP as u8, 25);
+        assert_eq!(Opcode::LPROG as u8, 26);
+        assert_eq!(Opcode::SPROG as u8, 27);
+        assert_eq!(Opcode::HALTR as u8, 28);
+        assert_eq!(Opcode::LOADW as u8, 29);
+        assert_eq!(Opcode::RMOV as u8, 30);
+        assert_eq!(Opcode::SYS as u8, 31);
+        assert_eq!(Opcode::JO as u8, 32);
+        assert_eq!(Opcode::JNO as u8, 33);
+        assert_eq!(Opcode::DBNZ as u8, 34);
+        assert_eq!(Opcode::CLOCK as u8, 35);
+        assert_eq!(Opcode::SELECT as u8, 36);
+        assert_eq!(Opcode::DIVMOD as u8, 37);
+        assert_eq!(Opcode::SHLI as u8, 38);
+        assert_eq!(Opcode::SHRI as u8, 39);
+        assert_eq!(Opcode::AMOADD as u8, 40);
+        assert_eq!(Opcode::BZERO as u8, 41);
+        assert_eq!(Opcode::JNEG as u8, 42);
+        assert_eq!(Opcode::JPOS as u8, 43);
+        assert_eq!(Opcode::ABSDIFF as u8, 44);
+        assert_eq!(Opcode::INARG as u8, 45);
+        assert_eq!(Opcode::FLAGLOG as u8, 46);
+        assert_eq!(Opcode::XORRANGE as u8, 47);
+        assert_eq!(Opcode::XCHG as u8, 48);
+        assert_eq!(Opcode::JMPFI as u8, 49);
+        assert_eq!(Opcode::JMPBI as u8, 50);
+        assert_eq!(Opcode::PUSH as u8, 51);
+        assert_eq!(Opcode::POP as u8, 52);
+        assert_eq!(Opcode::LMW as u8, 53);
+        assert_eq!(Opcode::SMW as u8, 54);
+        assert_eq!(Opcode::JMPI as u8, 55);
+        assert_eq!(Opcode::MOD as u8, 56);
+        assert_eq!(Opcode::SHL as u8, 57);
+        assert_eq!(Opcode::SHR as u8, 58);
+        assert_eq!(Opcode::CALLI as u8, 59);
+        assert_eq!(Opcode::LOADM as u8, 60);
+        assert_eq!(Opcode::STOREM as u8, 61);
+        assert_eq!(Opcode::NEG as u8, 62);
+        assert_eq!(Opcode::ABS as u8, 63);
+        assert_eq!(Opcode::MOV as u8, 64);
+        assert_eq!(Opcode::JEQI as u8, 65);
+        assert_eq!(Opcode::JNEQI as u8, 66);
+        assert_eq!(Opcode::JOI as u8, 67);
+        assert_eq!(Opcode::JNOI as u8, 68);
+        assert_eq!(Opcode::NOP as u8, 69);
         assert_eq!(Opcode::IGL as u8, 255);
     }
 
+    #[test]
+    fn test_instruction_size() {
+        assert_eq!(Opcode::LOAD.instruction_size(), 4);
+        assert_eq!(Opcode::HLT.instruction_size(), 4);
+        assert_eq!(Opcode::LOADW.instruction_size(), 8);
+    }
+
     #[test]
     fn test_opcode_from_str() {
-        assert_eq!(Opcode::HLT, Opcode::from("hlt"));
-        assert_eq!(Opcode::IGL, Opcode::from("hehehe"));
-        assert_eq!(Opcode::LOAD, Opcode::from("load"));
-        assert_eq!(Opcode::ADD, Opcode::from("add"));
-        assert_eq!(Opcode::MUL, Opcode::from("mul"));
-        assert_eq!(Opcode::SUB, Opcode::from("sub"));
-        assert_eq!(Opcode::DIV, Opcode::from("div"));
-        assert_eq!(Opcode::JMP, Opcode::from("jmp"));
-        assert_eq!(Opcode::JMPF, Opcode::from("jmpf"));
-        assert_eq!(Opcode::JMPB, Opcode::from("jmpb"));
-        assert_eq!(Opcode::EQ, Opcode::from("eq"));
-        assert_eq!(Opcode::NEQ, Opcode::from("neq"));
-        assert_eq!(Opcode::GT, Opcode::from("gt"));
-        assert_eq!(Opcode::GTE, Opcode::from("gte"));
-        assert_eq!(Opcode::LT, Opcode::from("lt"));
-        assert_eq!(Opcode::LTE, Opcode::from("lte"));
-        assert_eq!(Opcode::JEQ, Opcode::from("jeq"));
-        assert_eq!(Opcode::JNEQ, Opcode::from("jneq"));
-        assert_eq!(Opcode::ALOC, Opcode::from("aloc"));
-        assert_eq!(Opcode::INC, Opcode::from("inc"));
-        assert_eq!(Opcode::DEC, Opcode::from("dec"));
+        assert_eq!(Ok(Opcode::HLT), Opcode::try_from("hlt"));
+        assert_eq!(
+            Err(UnknownMnemonic("hehehe".to_string())),
+            Opcode::try_from("hehehe")
+        );
+        assert_eq!(
+            Opcode::try_from("hehehe").unwrap_err().to_string(),
+            "unknown mnemonic hehehe"
+        );
+    }
+
+    #[test]
+    fn test_from_mnemonic() {
+        assert_eq!(Ok(Opcode::JMPF), Opcode::try_from("jmpf"));
+        assert_eq!(Ok(Opcode::IGL), Opcode::try_from("IGL"));
+        assert!(Opcode::try_from("jmpx").is_err());
+        assert!(Opcode::try_from("").is_err());
+        assert_eq!(Ok(Opcode::LOAD), Opcode::try_from("load"));
+        assert_eq!(Ok(Opcode::ADD), Opcode::try_from("add"));
+        assert_eq!(Ok(Opcode::MUL), Opcode::try_from("mul"));
+        assert_eq!(Ok(Opcode::SUB), Opcode::try_from("sub"));
+        assert_eq!(Ok(Opcode::DIV), Opcode::try_from("div"));
+        assert_eq!(Ok(Opcode::JMP), Opcode::try_from("jmp"));
+        assert_eq!(Ok(Opcode::JMPF), Opcode::try_from("jmpf"));
+        assert_eq!(Ok(Opcode::JMPB), Opcode::try_from("jmpb"));
+        assert_eq!(Ok(Opcode::EQ), Opcode::try_from("eq"));
+        assert_eq!(Ok(Opcode::NEQ), Opcode::try_from("neq"));
+        assert_eq!(Ok(Opcode::GT), Opcode::try_from("gt"));
+        assert_eq!(Ok(Opcode::GTE), Opcode::try_from("gte"));
+        assert_eq!(Ok(Opcode::LT), Opcode::try_from("lt"));
+        assert_eq!(Ok(Opcode::LTE), Opcode::try_from("lte"));
+        assert_eq!(Ok(Opcode::JEQ), Opcode::try_from("jeq"));
+        assert_eq!(Ok(Opcode::JNEQ), Opcode::try_from("jneq"));
+        assert_eq!(Ok(Opcode::ALOC), Opcode::try_from("aloc"));
+        assert_eq!(Ok(Opcode::INC), Opcode::try_from("inc"));
+        assert_eq!(Ok(Opcode::DEC), Opcode::try_from("dec"));
+        assert_eq!(Ok(Opcode::BSWAP), Opcode::try_from("bswap"));
+        assert_eq!(Ok(Opcode::CALL), Opcode::try_from("call"));
+        assert_eq!(Ok(Opcode::RET), Opcode::try_from("ret"));
+        assert_eq!(Ok(Opcode::SETZ), Opcode::try_from("setz"));
+        assert_eq!(Ok(Opcode::SETNZ), Opcode::try_from("setnz"));
+        assert_eq!(Ok(Opcode::SP), Opcode::try_from("sp"));
+        assert_eq!(Ok(Opcode::LPROG), Opcode::try_from("lprog"));
+        assert_eq!(Ok(Opcode::SPROG), Opcode::try_from("sprog"));
+        assert_eq!(Ok(Opcode::HALTR), Opcode::try_from("haltr"));
+        assert_eq!(Ok(Opcode::LOADW), Opcode::try_from("loadw"));
+        assert_eq!(Ok(Opcode::RMOV), Opcode::try_from("rmov"));
+        assert_eq!(Ok(Opcode::SYS), Opcode::try_from("sys"));
+        assert_eq!(Ok(Opcode::JO), Opcode::try_from("jo"));
+        assert_eq!(Ok(Opcode::JNO), Opcode::try_from("jno"));
+        assert_eq!(Ok(Opcode::DBNZ), Opcode::try_from("dbnz"));
+        assert_eq!(Ok(Opcode::CLOCK), Opcode::try_from("clock"));
+        assert_eq!(Ok(Opcode::SELECT), Opcode::try_from("select"));
+        assert_eq!(Ok(Opcode::DIVMOD), Opcode::try_from("divmod"));
+        assert_eq!(Ok(Opcode::SHLI), Opcode::try_from("shli"));
+        assert_eq!(Ok(Opcode::SHRI), Opcode::try_from("shri"));
+        assert_eq!(Ok(Opcode::AMOADD), Opcode::try_from("amoadd"));
+        assert_eq!(Ok(Opcode::BZERO), Opcode::try_from("bzero"));
+        assert_eq!(Ok(Opcode::JNEG), Opcode::try_from("jneg"));
+        assert_eq!(Ok(Opcode::JPOS), Opcode::try_from("jpos"));
+        assert_eq!(Ok(Opcode::ABSDIFF), Opcode::try_from("absdiff"));
+        assert_eq!(Ok(Opcode::INARG), Opcode::try_from("inarg"));
+        assert_eq!(Ok(Opcode::FLAGLOG), Opcode::try_from("flaglog"));
+        assert_eq!(Ok(Opcode::XORRANGE), Opcode::try_from("xorrange"));
+        assert_eq!(Ok(Opcode::XCHG), Opcode::try_from("xchg"));
+        assert_eq!(Ok(Opcode::JMPFI), Opcode::try_from("jmpfi"));
+        assert_eq!(Ok(Opcode::JMPBI), Opcode::try_from("jmpbi"));
+        assert_eq!(Ok(Opcode::PUSH), Opcode::try_from("push"));
+        assert_eq!(Ok(Opcode::POP), Opcode::try_from("pop"));
+        assert_eq!(Ok(Opcode::LMW), Opcode::try_from("lmw"));
+        assert_eq!(Ok(Opcode::SMW), Opcode::try_from("smw"));
+        assert_eq!(Ok(Opcode::JMPI), Opcode::try_from("jmpi"));
+        assert_eq!(Ok(Opcode::MOD), Opcode::try_from("mod"));
+        assert_eq!(Ok(Opcode::SHL), Opcode::try_from("shl"));
+        assert_eq!(Ok(Opcode::SHR), Opcode::try_from("shr"));
+        assert_eq!(Ok(Opcode::CALLI), Opcode::try_from("calli"));
+        assert_eq!(Ok(Opcode::LOADM), Opcode::try_from("loadm"));
+        assert_eq!(Ok(Opcode::STOREM), Opcode::try_from("storem"));
+        assert_eq!(Ok(Opcode::NEG), Opcode::try_from("neg"));
+        assert_eq!(Ok(Opcode::ABS), Opcode::try_from("abs"));
+        assert_eq!(Ok(Opcode::MOV), Opcode::try_from("mov"));
+        assert_eq!(Ok(Opcode::JEQI), Opcode::try_from("jeqi"));
+        assert_eq!(Ok(Opcode::JNEQI), Opcode::try_from("jneqi"));
+        assert_eq!(Ok(Opcode::JOI), Opcode::try_from("joi"));
+        assert_eq!(Ok(Opcode::JNOI), Opcode::try_from("jnoi"));
+        assert_eq!(Ok(Opcode::NOP), Opcode::try_from("nop"));
     }
 }
@@ -1,5 +1,6 @@
-use crate::assembler::Assembler;
-use crate::vm::VM;
+use crate::assembler::header::BinaryHeader;
+use crate::assembler::{Assembler, AssemblerSection};
+use crate::vm::{HaltReason, VMError, MAX_REGISTERS, VM};
 use std;
 use std::fs;
 use std::io::{self, Write};
@@ -14,6 +15,16 @@ static PROMPT: &str = "\x1b[1;32miridium >>\x1b[0m ";
 #[cfg(windows)]
 static PROMPT: &str = "iridium >> ";
 
+/// Number of recently executed instructions shown by .vm.
+const PC_HISTORY_LEN: usize = 8;
+
+/// Max number of instructions that .check executes before giving up.
+const CHECK_STEP_LIMIT: usize = 1_000_000;
+
+/// Max number of instructions that .go executes before giving up, so that
+/// infinite loops don't hang the REPL.
+const GO_BUDGET: u64 = 10_000_000;
+
 /// Key structure for the Assembly REPL.
 pub struct REPL {
     // VM instance that executes the assembly.
@@ -21,14 +32,24 @@ pub struct REPL {
 
     // Assembler
     asm: Assembler,
+
+    // Lines of assembly typed in this session that assembled successfully.
+    source: Vec<String>,
+
+    // Number of .expect checks that passed and failed in this session.
+    expect_passed: usize,
+    expect_failed: usize,
 }
 
 impl REPL {
     /// Create a new REPL instance.
     pub fn new() -> Self {
         REPL {
-            vm: VM::new(),
+            vm: VM::new().with_pc_history(PC_HISTORY_LEN),
             asm: Assembler::new(),
+            source: vec![],
+            expect_passed: 0,
+            expect_failed: 0,
         }
     }
 
@@ -63,7 +84,7 @@ impl REPL {
                     rl.add_history_entry(line.as_str());
                     match line.as_str() {
                         ".reset" => {
-                            self.vm = VM::new();
+                            self.vm = VM::new().with_pc_history(PC_HISTORY_LEN);
                             println!("Resetting VM state. Everything should be clean now.");
                         }
                         ".q" | ".quit" => {
@@ -81,28 +102,93 @@ impl REPL {
                         ".vm" => {
                             self.vm.dump_state();
                         }
+                        ".sections" => {
+                            print!("{}", self.format_sections());
+                        }
+                        ".heap" => {
+                            print!("{}", self.vm.hexdump_heap(0, usize::MAX));
+                        }
                         ".load" => {
                             self.load_file();
                         }
                         ".n" | ".next" => {
-                            self.vm.run_once();
+                            let result = self.vm.run_once();
+                            self.report_step(result);
+                        }
+                        ".ns" => {
+                            let before = self.vm.snapshot();
+                            let result = self.vm.run_once();
+                            for change in before.diff(&self.vm.snapshot()) {
+                                println!("{}", change);
+                            }
+                            self.report_step(result);
+                        }
+                        ".over" => {
+                            let result = self.vm.step_over(Some(GO_BUDGET));
+                            self.report_step(result);
                         }
                         ".g" | ".go" => {
-                            self.vm.run();
+                            let result = self.vm.run_until_breakpoint(Some(GO_BUDGET));
+                            if let Ok(None) = result {
+                                println!("Paused at breakpoint pc={}", self.vm.pc());
+                            }
+                            self.report_step(result);
+                        }
+                        ".check" => {
+                            println!("{}", check_program(self.vm.program(), CHECK_STEP_LIMIT));
                         }
                         ".h" | ".help" => {
                             self.print_help();
                         }
+                        ".expect summary" => {
+                            println!(
+                                "{} passed, {} failed",
+                                self.expect_passed, self.expect_failed
+                            );
+                        }
+                        cmd if is_command(cmd, ".expect") => {
+                            match parse_expect(&cmd[".expect".len()..]) {
+                                Some((reg, expected)) => {
+                                    println!("{}", self.check_expect(reg, expected));
+                                }
+                                None => println!("Usage: .expect $<register> = <value>"),
+                            }
+                        }
+                        cmd if is_command(cmd, ".break") => {
+                            match cmd[".break".len()..].trim().parse() {
+                                Ok(pc) => {
+                                    self.vm.add_breakpoint(pc);
+                                    println!("Breakpoint set at pc={}", pc);
+                                }
+                                Err(_) => println!("Usage: .break <offset>"),
+                            }
+                        }
+                        cmd if is_command(cmd, ".save") => {
+                            let path = match cmd[".save".len()..].trim() {
+                                "" => prompt_path(),
+                                path => path.to_string(),
+                            };
+                            match self.save_program(&path) {
+                                Ok(()) => println!("Saved program to {}", path),
+                                Err(e) => println!("Error: {}", e),
+                            }
+                        }
+                        cmd if is_command(cmd, ".export") => {
+                            let path = cmd[".export".len()..].trim();
+                            if path.is_empty() {
+                                println!("Usage: .export <path>");
+                            } else {
+                                match self.export_source(path) {
+                                    Ok(()) => println!("Exported session to {}", path),
+                                    Err(e) => println!("Error: {}", e),
+                                }
+                            }
+                        }
                         inst => {
                             if inst.starts_with(".") {
                                 println!("Unrecognized instruction. Use .help for detailed help.");
                             } else {
-                                let bytecode = self
-                                    .asm
-                                    .assemble(line.as_str())
-                                    .expect("Failed to parse program.");
-                                self.vm.add_bytes(&bytecode);
-                                self.vm.run_once();
+                                self.execute_line(inst);
                             }
                         }
                     }
@@ -129,32 +215,149 @@ impl REPL {
         println!(".history  See the command history.");
         println!(".regs     Dump registers.");
         println!(".vm       Dump VM state excluding registers.");
+        println!(".heap     Hex dump of the heap.");
+        println!(".sections Show the sections of the last assembled program.");
         println!(".load     Load an assembly file. It prompts for the file path.");
         println!(".n        Execute next instruction.");
-        println!(".go       Execute rest of the program.");
+        println!(".ns       Execute next instruction and show what it changed.");
+        println!(".over     Execute next instruction, stepping over subroutine calls.");
+        println!(".go       Execute rest of the program or until a breakpoint is hit.");
+        println!(".break    Pause .go before the instruction at an offset e.g. .break 72");
+        println!(".check    Run the program from scratch and report whether it halts cleanly.");
+        println!(
+            ".save     Save the program's bytecode so it can be run later e.g. .save prog.iridium"
+        );
+        println!(".export   Save the assembly typed in this session e.g. .export prog.iasm");
+        println!(".expect   Check a register's value e.g. .expect $3 = 50");
+        println!("          Use .expect summary to see how many checks passed.");
         println!(".help     Print this help message.");
         println!(".quit     Quit the REPL. You can also use Ctrl-C or Ctrl-D.");
     }
 
-    fn load_file(&mut self) {
-        print!("Please enter file path: ");
-        // stdout is line-buffered and print! doesn't flush.
-        io::stdout().flush().expect("Failed to flush stdout.");
+    // Assembles a typed line, appends its code to the VM's program and
+    // executes it.
+    fn execute_line(&mut self, line: &str) {
+        let bytecode = match self.asm.assemble(line) {
+            Ok(bytecode) => bytecode,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        };
+        self.print_warnings();
+        self.record_source(line);
+
+        // Every line is assembled into a complete executable but the VM's
+        // program only needs one header.
+        if let Err(e) = self.vm.append_code(code_section(&bytecode)) {
+            println!("Error: {}", e);
+            return;
+        }
+        let result = self.vm.run_once();
+        self.report_step(result);
+    }
+
+    // Reports the outcome of executing a single step. Nothing is printed if
+    // the program can continue.
+    fn report_step(&self, result: Result<Option<HaltReason>, VMError>) {
+        let result = match result {
+            Ok(Some(reason)) => Ok(reason),
+            Ok(None) => return,
+            Err(e) => Err(e),
+        };
+        println!("{}", outcome_message(&result, self.vm.pc()));
+    }
+
+    // Describes the sections of the last assembled program, one per line.
+    fn format_sections(&self) -> String {
+        let mut result = String::new();
+        for section in self.asm.sections() {
+            let (kind, section) = match section {
+                AssemblerSection::Code(section) => ("code", section),
+                AssemblerSection::Data(section) => ("data", section),
+                AssemblerSection::Unknown => continue,
+            };
+            let field = |value: Option<usize>| value.map_or("?".to_string(), |v| v.to_string());
+            result.push_str(&format!(
+                "{:<6} start: {:<6} size: {}\n",
+                kind,
+                field(section.start()),
+                field(section.size())
+            ));
+        }
+        result
+    }
+
+    // Compares a register against the expected value and records the outcome
+    // for .expect summary.
+    fn check_expect(&mut self, reg: usize, expected: i32) -> String {
+        let actual = self.vm.register(reg);
+        if actual == expected {
+            self.expect_passed += 1;
+            format!("PASS: ${} = {}", reg, actual)
+        } else {
+            self.expect_failed += 1;
+            format!("FAIL: ${} = {}, expected {}", reg, actual, expected)
+        }
+    }
+
+    // Remembers a line of assembly for .export. REPL commands and blank lines
+    // aren't part of the program.
+    fn record_source(&mut self, line: &str) {
+        if !line.starts_with('.') && !line.trim().is_empty() {
+            self.source.push(line.to_string());
+        }
+    }
+
+    // Writes the assembly typed in this session to the specified file.
+    fn export_source(&self, path: &str) -> io::Result<()> {
+        let mut contents = self.source.join("\n");
+        contents.push('\n');
+        fs::write(path, contents)
+    }
 
-        let mut file = String::new();
-        io::stdin()
-            .read_line(&mut file)
-            .expect("Failed to read file name.");
+    // Writes the VM's program, header included, to the specified file so
+    // that it can be run later with VM::load_from_file.
+    fn save_program(&self, path: &str) -> io::Result<()> {
+        if self.vm.program().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "there is no program to save",
+            ));
+        }
+        fs::write(path, self.vm.program())
+    }
 
-        // read_line includes the ending newline character.
-        let file = file.trim();
-        let contents = fs::read_to_string(file).expect("Failed to read file.");
+    fn load_file(&mut self) {
+        let file = prompt_path();
+        let contents = match fs::read_to_string(&file) {
+            Ok(contents) => contents,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        };
+
+        // The file replaces the program so that the VM's program never ends
+        // up with more than one header.
+        let bytecode = match self.asm.assemble(&contents) {
+            Ok(bytecode) => bytecode,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        };
+        self.print_warnings();
+        if let Err(e) = self.vm.load_program(&bytecode) {
+            println!("Error: {}", e);
+        }
+    }
 
-        let bytecode = self
-            .asm
-            .assemble(&contents)
-            .expect("Failed to assemble program.");
-        self.vm.add_bytes(&bytecode);
+    // Prints the warnings reported while assembling the last program.
+    fn print_warnings(&self) {
+        for w in self.asm.warnings() {
+            println!("Warning: {}", w);
+        }
     }
 
     fn dump_registers(&self) {
@@ -164,3 +367,312 @@ impl REPL {
         }
     }
 }
+
+// Asks the user for a file path.
+fn prompt_path() -> String {
+    print!("Please enter file path: ");
+    // stdout is line-buffered and print! doesn't flush.
+    io::stdout().flush().expect("Failed to flush stdout.");
+
+    let mut file = String::new();
+    io::stdin()
+        .read_line(&mut file)
+        .expect("Failed to read file name.");
+
+    // read_line includes the ending newline character.
+    file.trim().to_string()
+}
+
+// Checks whether the line invokes the named command, i.e. the name is
+// followed by whitespace or nothing at all. `.exportx` isn't `.export`.
+fn is_command(line: &str, name: &str) -> bool {
+    match line.strip_prefix(name) {
+        Some(rest) => rest.is_empty() || rest.starts_with(char::is_whitespace),
+        None => false,
+    }
+}
+
+// Parses the arguments of .expect i.e. "$3 = 50" into the register and the
+// expected value.
+fn parse_expect(args: &str) -> Option<(usize, i32)> {
+    let mut parts = args.splitn(2, '=');
+    let reg = parts.next()?.trim().strip_prefix('$')?.parse().ok()?;
+    let expected = parts.next()?.trim().parse().ok()?;
+    if reg < MAX_REGISTERS {
+        Some((reg, expected))
+    } else {
+        None
+    }
+}
+
+// Describes how a program stopped. pc is where the VM stopped and is only
+// reported for errors.
+fn outcome_message(result: &Result<HaltReason, VMError>, pc: usize) -> String {
+    match result {
+        Ok(HaltReason::Halted) => "Program halted normally".to_string(),
+        Ok(HaltReason::HaltedWithResult) => "Program halted with a result".to_string(),
+        Ok(HaltReason::EndOfProgram) => "Program exhausted without HLT".to_string(),
+        Err(e) => format!("Error: {} at pc={}", e, pc),
+    }
+}
+
+// Returns the code section of an executable produced by the assembler.
+fn code_section(executable: &[u8]) -> &[u8] {
+    let header = match BinaryHeader::parse(executable) {
+        Ok(header) => header,
+        Err(_) => return &[],
+    };
+    let end = match header.data_start as usize {
+        0 => executable.len(),
+        start => start,
+    };
+    &executable[header.entry_point()..end]
+}
+
+// Runs the program on a fresh VM for at most `limit` instructions and
+// reports whether it halted cleanly. The REPL's VM isn't touched.
+fn check_program(program: &[u8], limit: usize) -> String {
+    let mut vm = VM::new();
+    vm.add_bytes(program);
+    match vm.run_steps_reporting(limit, |_| ()) {
+        Ok(Some(HaltReason::Halted)) | Ok(Some(HaltReason::HaltedWithResult)) => {
+            "PASS: program halted cleanly".to_string()
+        }
+        Ok(Some(HaltReason::EndOfProgram)) => {
+            "FAIL: program ran off the end without HLT".to_string()
+        }
+        Ok(None) => format!("FAIL: program didn't halt within {} instructions", limit),
+        Err(e) => format!("FAIL: error: {} at pc={}", e, vm.pc()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::BIN_HEADER_LENGTH;
+
+    #[test]
+    fn test_outcome_message() {
+        assert_eq!(
+            outcome_message(&Ok(HaltReason::Halted), 68),
+            "Program halted normally"
+        );
+        assert_eq!(
+            outcome_message(&Ok(HaltReason::HaltedWithResult), 68),
+            "Program halted with a result"
+        );
+        assert_eq!(
+            outcome_message(&Ok(HaltReason::EndOfProgram), 68),
+            "Program exhausted without HLT"
+        );
+
+        let errors = [
+            (VMError::InvalidHeader, "invalid binary header"),
+            (
+                VMError::UnsupportedVersion(7),
+                "unsupported executable version 7",
+            ),
+            (VMError::IllegalOpcode(200), "illegal opcode 200"),
+            (VMError::StackOverflow, "call stack overflow"),
+            (VMError::StackUnderflow, "stack underflow"),
+            (
+                VMError::InvalidProgramAddress(9000),
+                "program address 9000 is out of bounds",
+            ),
+            (
+                VMError::InvalidRegisterRange { base: 30, count: 4 },
+                "register range $30..$34 is out of bounds",
+            ),
+            (
+                VMError::InvalidRegister(40),
+                "register $40 is out of bounds",
+            ),
+            (VMError::UnknownSyscall(9), "unknown syscall 9"),
+            (VMError::DivisionByZero, "division by zero"),
+            (
+                VMError::UninitializedRegister(5),
+                "register $5 is read before being written",
+            ),
+            (
+                VMError::InvalidSharedAddress(6),
+                "shared heap address 6 is out of bounds",
+            ),
+            (
+                VMError::InvalidHeapAddress(7),
+                "heap address 7 is out of bounds",
+            ),
+            (
+                VMError::HeapLimitExceeded(1024),
+                "heap size 1024 exceeds the limit",
+            ),
+            (
+                VMError::NegativeAllocation(-4),
+                "can't allocate a negative size -4",
+            ),
+            (
+                VMError::BudgetExceeded(100),
+                "still running after 100 instructions",
+            ),
+        ];
+        for (e, message) in errors.iter() {
+            assert_eq!(
+                outcome_message(&Err(e.clone()), 72),
+                format!("Error: {} at pc=72", message)
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_command() {
+        assert!(is_command(".export", ".export"));
+        assert!(is_command(".export out.iasm", ".export"));
+        assert!(is_command(".break\t12", ".break"));
+        assert!(!is_command(".exportx out.iasm", ".export"));
+        assert!(!is_command(".saved", ".save"));
+        assert!(!is_command(".expected $0 = 1", ".expect"));
+        assert!(!is_command("save", ".save"));
+    }
+
+    #[test]
+    fn test_parse_expect() {
+        assert_eq!(parse_expect(" $3 = 50"), Some((3, 50)));
+        assert_eq!(parse_expect("$0=-7"), Some((0, -7)));
+        assert_eq!(parse_expect(" 3 = 50"), None);
+        assert_eq!(parse_expect(" $3 50"), None);
+        assert_eq!(parse_expect(" $3 = x"), None);
+        assert_eq!(parse_expect(" $32 = 1"), None);
+    }
+
+    #[test]
+    fn test_check_expect() {
+        let mut repl = REPL::new();
+        repl.vm.set_register(3, 50).unwrap();
+        assert_eq!(repl.check_expect(3, 50), "PASS: $3 = 50");
+        assert_eq!(repl.check_expect(3, 49), "FAIL: $3 = 50, expected 49");
+        assert_eq!(repl.check_expect(0, 0), "PASS: $0 = 0");
+        assert_eq!((repl.expect_passed, repl.expect_failed), (2, 1));
+    }
+
+    #[test]
+    fn test_record_source() {
+        let mut repl = REPL::new();
+        for line in &[
+            "load $0 #10",
+            ".regs",
+            "",
+            "loop: inc $0",
+            ".export x.iasm",
+            "hlt",
+        ] {
+            repl.record_source(line);
+        }
+        assert_eq!(repl.source, vec!["load $0 #10", "loop: inc $0", "hlt"]);
+    }
+
+    #[test]
+    fn test_format_sections() {
+        let mut repl = REPL::new();
+        repl.asm
+            .assemble(".data\nfirst: .word #1\n.code\nload $0 @first\nloadw $1 #100000\nhlt\n.data\n.long #2")
+            .unwrap();
+        assert_eq!(
+            repl.format_sections(),
+            "data   start: 0      size: 2\n\
+             code   start: 64     size: 16\n\
+             data   start: 2      size: 4\n"
+        );
+    }
+
+    #[test]
+    fn test_check_program() {
+        let mut repl = REPL::new();
+        let mut check = |source: &str| {
+            let program = repl.asm.assemble(source).unwrap();
+            repl.vm = VM::new();
+            repl.vm.add_bytes(&program);
+            let message = check_program(repl.vm.program(), 100);
+            assert_eq!(repl.vm.pc(), 0);
+            message
+        };
+
+        assert_eq!(check("load $0 #1\nhlt"), "PASS: program halted cleanly");
+        assert_eq!(
+            check("load $0 #1"),
+            "FAIL: program ran off the end without HLT"
+        );
+        assert_eq!(
+            check("load $0 #1\nigl"),
+            "FAIL: error: illegal opcode 255 at pc=69"
+        );
+        assert_eq!(
+            check("load $0 #68\njmp $0"),
+            "FAIL: program didn't halt within 100 instructions"
+        );
+    }
+
+    #[test]
+    fn test_export_source() {
+        let mut repl = REPL::new();
+        repl.record_source("load $0 #10");
+        repl.record_source("hlt");
+
+        let path = std::env::temp_dir().join("iridium_test_export_source.iasm");
+        let path = path.to_str().unwrap();
+        repl.export_source(path).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "load $0 #10\nhlt\n");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_execute_line() {
+        let mut repl = REPL::new();
+        repl.execute_line("load $0 #5");
+        repl.execute_line("load $1 #7");
+        assert_eq!((repl.vm.register(0), repl.vm.register(1)), (5, 7));
+        assert_eq!(repl.vm.program().len(), BIN_HEADER_LENGTH + 8);
+        assert_eq!(repl.vm.pc(), BIN_HEADER_LENGTH + 8);
+
+        // Lines that don't assemble aren't added to the program.
+        repl.execute_line("load $0 #5 #6 #7");
+        assert_eq!(repl.vm.program().len(), BIN_HEADER_LENGTH + 8);
+        assert_eq!(repl.source, vec!["load $0 #5", "load $1 #7"]);
+    }
+
+    #[test]
+    fn test_save_program() {
+        let mut repl = REPL::new();
+        let path = std::env::temp_dir().join("iridium_test_save_program.iridium");
+        let path = path.to_str().unwrap();
+        assert!(repl.save_program(path).is_err());
+
+        let bytecode = repl.asm.assemble("load $0 #10\nload $1 #-2\nhlt").unwrap();
+        repl.vm.add_bytes(&bytecode);
+        repl.save_program(path).unwrap();
+        assert_eq!(fs::read(path).unwrap(), repl.vm.program());
+
+        let mut vm = VM::new();
+        let loaded = vm.load_from_file(path);
+        fs::remove_file(path).unwrap();
+        loaded.unwrap();
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!((vm.register(0), vm.register(1)), (10, -2));
+    }
+
+    #[test]
+    fn test_save_typed_lines() {
+        let mut repl = REPL::new();
+        for line in &["load $0 #3", "load $1 #4", "add $0 $1 $2", "hlt"] {
+            repl.execute_line(line);
+        }
+        let path = std::env::temp_dir().join("iridium_test_save_typed_lines.iridium");
+        let path = path.to_str().unwrap();
+        repl.save_program(path).unwrap();
+
+        let mut vm = VM::new();
+        let loaded = vm.load_from_file(path);
+        fs::remove_file(path).unwrap();
+        loaded.unwrap();
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(2), 7);
+    }
+}
@@ -0,0 +1,83 @@
+/// Helpers that make it easy to write end-to-end tests against the
+/// assembler and the VM.
+use crate::assembler::assembler_errors::AssemblerError;
+use crate::assembler::Assembler;
+use crate::vm::{VMError, VM};
+
+#[derive(Debug)]
+pub enum Error {
+    /// The source failed to assemble. Parse failures are reported as a
+    /// `ParseError` in the list.
+    Assemble(Vec<AssemblerError>),
+
+    /// The program failed while running on the VM.
+    Run(VMError),
+}
+
+/// Assembles and runs the source, returning the final register file.
+pub fn run_source(src: &str) -> Result<Vec<i32>, Error> {
+    let mut vm = load_source(src)?;
+    vm.run().map_err(Error::Run)?;
+    Ok(vm.registers().collect())
+}
+
+/// Like run_source() but also returns everything the program printed.
+pub fn run_source_capturing(src: &str) -> Result<(Vec<i32>, String), Error> {
+    let mut vm = load_source(src)?;
+    vm.capture_output();
+    vm.run().map_err(Error::Run)?;
+    Ok((vm.registers().collect(), vm.take_output()))
+}
+
+// Assembles the source into a new VM.
+fn load_source(src: &str) -> Result<VM, Error> {
+    let mut asm = Assembler::new();
+    let program = match asm.assemble(src) {
+        Ok(program) => program,
+        Err(_) => return Err(Error::Assemble(asm.errors().to_vec())),
+    };
+
+    let mut vm = VM::new();
+    vm.add_bytes(&program);
+    Ok(vm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_source() {
+        let registers = run_source("load $0 #5\nload $1 #7\nadd $0 $1 $2\nhlt").unwrap();
+        assert_eq!(&registers[..3], &[5, 7, 12]);
+    }
+
+    #[test]
+    fn test_run_source_capturing() {
+        let (registers, output) =
+            run_source_capturing("load $0 #42\nsys #0\nload $0 #7\nsys #0\nhlt").unwrap();
+        assert_eq!(registers[0], 7);
+        assert_eq!(output, "42\n7\n");
+    }
+
+    #[test]
+    fn test_run_source_error() {
+        match run_source("jmp @nowhere") {
+            Err(Error::Assemble(errors)) => assert_eq!(errors.len(), 1),
+            _ => panic!("Expected an assembler error."),
+        }
+
+        match run_source("$0") {
+            Err(Error::Assemble(errors)) => match errors.as_slice() {
+                [AssemblerError::ParseError { .. }] => (),
+                _ => panic!("Expected a parse error, got {:?}.", errors),
+            },
+            _ => panic!("Expected an assembler error."),
+        }
+
+        match run_source("ret") {
+            Err(Error::Run(e)) => assert_eq!(e, VMError::StackUnderflow),
+            _ => panic!("Expected a VM error."),
+        }
+    }
+}
@@ -0,0 +1,771 @@
+use std::fmt;
+
+use super::{HaltReason, VMError, INPUT_BUFFER_BASE, MAX_REGISTERS, VM};
+use crate::opcode::Opcode;
+
+/// Implements the semantics of the instructions. The VM decodes the opcode
+/// and calls the method for its group, which is responsible for reading the
+/// operands through the VM and advancing the program counter past the
+/// instruction. It allows swapping the interpreter for other backends, e.g.
+/// one that traces execution.
+pub trait Executor: fmt::Debug + Send {
+    /// Instructions that halt the program or transfer control: halts, jumps,
+    /// branches and subroutine calls. Returns the reason if the program
+    /// has terminated.
+    fn control(&mut self, vm: &mut VM, opcode: Opcode) -> Result<Option<HaltReason>, VMError>;
+
+    /// Arithmetic, shift and byte manipulation instructions.
+    fn arithmetic(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError>;
+
+    /// Instructions that set the equal flag or consume it.
+    fn comparison(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError>;
+
+    /// Instructions that load constants or move values between registers,
+    /// the heap and the program.
+    fn memory(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError>;
+
+    /// Instructions that work on the operand stack.
+    fn stack(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError>;
+
+    /// Syscalls and other instructions that interact with the host.
+    fn system(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError>;
+}
+
+/// Executor that interprets the instructions directly. It's what the VM
+/// uses by default.
+#[derive(Debug, Default)]
+pub struct InterpreterExecutor;
+
+impl Executor for InterpreterExecutor {
+    fn control(&mut self, vm: &mut VM, opcode: Opcode) -> Result<Option<HaltReason>, VMError> {
+        match opcode {
+            Opcode::HLT => {
+                return Ok(Some(HaltReason::Halted));
+            }
+            Opcode::HALTR => {
+                vm.has_result = true;
+                return Ok(Some(HaltReason::HaltedWithResult));
+            }
+            Opcode::JMP => {
+                let target = vm.read_register();
+                vm.pc = target as usize;
+            }
+            Opcode::JMPF => {
+                let target = vm.read_register();
+                vm.pc += target as usize;
+            }
+            Opcode::JMPB => {
+                let target = vm.read_register();
+                vm.pc -= target as usize;
+            }
+            Opcode::JMPI => {
+                let target = vm.next_16_bits();
+                vm.pc = usize::from(target);
+            }
+            Opcode::JMPFI => {
+                let start = vm.pc - 1;
+                let offset = usize::from(vm.next_16_bits());
+                vm.pc = start + offset;
+            }
+            Opcode::JMPBI => {
+                let start = vm.pc - 1;
+                let offset = usize::from(vm.next_16_bits());
+                vm.pc = match start.checked_sub(offset) {
+                    Some(target) => target,
+                    None => return Err(VMError::InvalidProgramAddress(start)),
+                };
+            }
+            Opcode::JEQI | Opcode::JNEQI | Opcode::JOI | Opcode::JNOI => {
+                let target = usize::from(vm.next_16_bits());
+                vm.skip_bytes(1);
+                let taken = match opcode {
+                    Opcode::JEQI => vm.equal_flag,
+                    Opcode::JNEQI => !vm.equal_flag,
+                    Opcode::JOI => vm.overflow_flag,
+                    _ => !vm.overflow_flag,
+                };
+                if taken {
+                    vm.pc = target;
+                }
+            }
+            Opcode::JEQ => {
+                let target = vm.read_register();
+                vm.skip_bytes(2);
+                if vm.equal_flag {
+                    vm.pc = target as usize;
+                }
+            }
+            Opcode::JNEQ => {
+                let target = vm.read_register();
+                vm.skip_bytes(2);
+                if !vm.equal_flag {
+                    vm.pc = target as usize;
+                }
+            }
+            Opcode::JO => {
+                let target = vm.read_register();
+                vm.skip_bytes(2);
+                if vm.overflow_flag {
+                    vm.pc = target as usize;
+                }
+            }
+            Opcode::JNO => {
+                let target = vm.read_register();
+                vm.skip_bytes(2);
+                if !vm.overflow_flag {
+                    vm.pc = target as usize;
+                }
+            }
+            Opcode::JNEG => {
+                let value = vm.read_register();
+                let target = vm.read_register();
+                vm.skip_bytes(1);
+                if value < 0 {
+                    vm.pc = target as usize;
+                }
+            }
+            Opcode::JPOS => {
+                let value = vm.read_register();
+                let target = vm.read_register();
+                vm.skip_bytes(1);
+                if value > 0 {
+                    vm.pc = target as usize;
+                }
+            }
+            Opcode::DBNZ => {
+                let counter = vm.next_8_bits() as usize;
+                let target = vm.read_register();
+                vm.skip_bytes(1);
+
+                let value = vm.get_register(counter).wrapping_sub(1);
+                vm.write_register(counter, value);
+                if value != 0 {
+                    vm.pc = target as usize;
+                }
+            }
+            Opcode::CALL | Opcode::CALLI => {
+                let (target, padding) = if opcode == Opcode::CALL {
+                    (vm.read_register() as usize, 2)
+                } else {
+                    (usize::from(vm.next_16_bits()), 1)
+                };
+
+                if vm.call_stack.len() >= vm.max_call_depth {
+                    return Err(VMError::StackOverflow);
+                }
+
+                // Skip over the padding so that the return address points to
+                // the next instruction.
+                vm.skip_bytes(padding);
+                vm.call_stack.push(vm.pc);
+                vm.pc = target;
+            }
+            Opcode::NOP => vm.skip_bytes(3),
+            Opcode::RET => match vm.call_stack.pop() {
+                Some(addr) => vm.pc = addr,
+                None => return Err(VMError::StackUnderflow),
+            },
+            _ => return Err(VMError::IllegalOpcode(opcode as u8)),
+        }
+        Ok(None)
+    }
+
+    fn arithmetic(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError> {
+        match opcode {
+            Opcode::SHLI => {
+                let i = vm.next_8_bits() as usize;
+                let amount = u32::from(vm.next_16_bits());
+                let (value, overflow) = vm.get_register(i).overflowing_shl(amount);
+                vm.write_register(i, value);
+                vm.overflow_flag = overflow;
+            }
+            Opcode::SHRI => {
+                let i = vm.next_8_bits() as usize;
+                let amount = u32::from(vm.next_16_bits());
+                let (value, overflow) = vm.get_register(i).overflowing_shr(amount);
+                vm.write_register(i, value);
+                vm.overflow_flag = overflow;
+            }
+            Opcode::SHL | Opcode::SHR => {
+                let dst = vm.next_8_bits() as usize;
+                let value = vm.read_register();
+                let amount = vm.read_register() as u32;
+                let (value, overflow) = if opcode == Opcode::SHL {
+                    value.overflowing_shl(amount)
+                } else {
+                    value.overflowing_shr(amount)
+                };
+                vm.write_register(dst, value);
+                vm.overflow_flag = overflow;
+            }
+            Opcode::ABSDIFF => {
+                let reg1 = vm.read_register();
+                let reg2 = vm.read_register();
+                let dst = vm.next_8_bits() as usize;
+                let (difference, sub_overflow) = reg1.overflowing_sub(reg2);
+                let (result, abs_overflow) = difference.overflowing_abs();
+                vm.write_register(dst, result);
+                vm.overflow_flag = sub_overflow || abs_overflow;
+            }
+            Opcode::ADD => {
+                let reg1 = vm.read_register();
+                let reg2 = vm.read_register();
+                let (result, overflow) = reg1.overflowing_add(reg2);
+                let dst = vm.next_8_bits() as usize;
+                vm.write_register(dst, result);
+                vm.overflow_flag = overflow;
+            }
+            Opcode::SUB => {
+                let reg1 = vm.read_register();
+                let reg2 = vm.read_register();
+                let (result, overflow) = reg1.overflowing_sub(reg2);
+                let dst = vm.next_8_bits() as usize;
+                vm.write_register(dst, result);
+                vm.overflow_flag = overflow;
+            }
+            Opcode::MUL => {
+                let reg1 = vm.read_register();
+                let reg2 = vm.read_register();
+                let (result, overflow) = reg1.overflowing_mul(reg2);
+                let dst = vm.next_8_bits() as usize;
+                vm.write_register(dst, result);
+                vm.overflow_flag = overflow;
+            }
+            Opcode::DIV => {
+                let reg1 = vm.read_register();
+                let reg2 = vm.read_register();
+                let dst = vm.next_8_bits() as usize;
+                if reg2 == 0 {
+                    return Err(VMError::DivisionByZero);
+                }
+                let (result, overflow) = reg1.overflowing_div(reg2);
+                vm.write_register(dst, result);
+                vm.remainder = reg1.wrapping_rem(reg2) as u32;
+                vm.overflow_flag = overflow;
+            }
+            Opcode::MOD => {
+                let reg1 = vm.read_register();
+                let reg2 = vm.read_register();
+                let dst = vm.next_8_bits() as usize;
+                if reg2 == 0 {
+                    return Err(VMError::DivisionByZero);
+                }
+                let (result, overflow) = reg1.overflowing_rem(reg2);
+                vm.write_register(dst, result);
+                vm.overflow_flag = overflow;
+            }
+            Opcode::DIVMOD => {
+                let num = vm.read_register();
+                let den = vm.read_register();
+                let dst = vm.next_8_bits() as usize;
+                if dst + 1 >= MAX_REGISTERS {
+                    return Err(VMError::InvalidRegisterRange {
+                        base: dst,
+                        count: 2,
+                    });
+                }
+                if den == 0 {
+                    return Err(VMError::DivisionByZero);
+                }
+
+                let (quotient, overflow) = num.overflowing_div(den);
+                vm.write_register(dst, quotient);
+                vm.write_register(dst + 1, num.wrapping_rem(den));
+                vm.overflow_flag = overflow;
+            }
+            Opcode::INC => {
+                let i = vm.next_8_bits() as usize;
+                let (value, overflow) = vm.get_register(i).overflowing_add(1);
+                vm.write_register(i, value);
+                vm.overflow_flag = overflow;
+                vm.skip_bytes(2);
+            }
+            Opcode::DEC => {
+                let i = vm.next_8_bits() as usize;
+                let (value, overflow) = vm.get_register(i).overflowing_sub(1);
+                vm.write_register(i, value);
+                vm.overflow_flag = overflow;
+                vm.skip_bytes(2);
+            }
+            Opcode::BSWAP => {
+                let dst = vm.next_8_bits() as usize;
+                let value = vm.read_register() as u32;
+                vm.write_register(dst, value.swap_bytes() as i32);
+                vm.skip_bytes(1);
+            }
+            Opcode::NEG | Opcode::ABS => {
+                let dst = vm.next_8_bits() as usize;
+                let value = vm.read_register();
+                let result = if opcode == Opcode::NEG {
+                    value.checked_neg()
+                } else {
+                    value.checked_abs()
+                };
+                vm.write_register(dst, result.unwrap_or(i32::MAX));
+                vm.overflow_flag = result.is_none();
+                vm.skip_bytes(1);
+            }
+            _ => return Err(VMError::IllegalOpcode(opcode as u8)),
+        }
+        Ok(())
+    }
+
+    fn comparison(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError> {
+        match opcode {
+            // Equality related instructions are kind of special given that they don't
+            //
+            // consumes all 4 bytes (like ADD/SUB) nor it manipulates the
+            // PC (JMP etc) so we'll skip over the next byte to make the instruction
+            // length evenly 4.
+            //
+            Opcode::EQ => {
+                let r1 = vm.read_register();
+                let r2 = vm.read_register();
+
+                if r1 == r2 {
+                    vm.equal_flag = true;
+                } else {
+                    vm.equal_flag = false;
+                }
+
+                // Skip over next byte to align the PC with 4 byte.
+                vm.next_8_bits();
+            }
+            Opcode::NEQ => {
+                let r1 = vm.read_register();
+                let r2 = vm.read_register();
+
+                if r1 != r2 {
+                    vm.equal_flag = true;
+                } else {
+                    vm.equal_flag = false;
+                }
+
+                // Skip over next byte to align the PC with 4 byte.
+                vm.next_8_bits();
+            }
+            Opcode::GT => {
+                let r1 = vm.read_register();
+                let r2 = vm.read_register();
+
+                if r1 > r2 {
+                    vm.equal_flag = true;
+                } else {
+                    vm.equal_flag = false;
+                }
+
+                // Skip over next byte to align the PC with 4 byte.
+                vm.next_8_bits();
+            }
+            Opcode::GTE => {
+                let r1 = vm.read_register();
+                let r2 = vm.read_register();
+
+                if r1 >= r2 {
+                    vm.equal_flag = true;
+                } else {
+                    vm.equal_flag = false;
+                }
+
+                // Skip over next byte to align the PC with 4 byte.
+                vm.next_8_bits();
+            }
+            Opcode::LT => {
+                let r1 = vm.read_register();
+                let r2 = vm.read_register();
+
+                if r1 < r2 {
+                    vm.equal_flag = true;
+                } else {
+                    vm.equal_flag = false;
+                }
+
+                // Skip over next byte to align the PC with 4 byte.
+                vm.next_8_bits();
+            }
+            Opcode::LTE => {
+                let r1 = vm.read_register();
+                let r2 = vm.read_register();
+
+                if r1 <= r2 {
+                    vm.equal_flag = true;
+                } else {
+                    vm.equal_flag = false;
+                }
+
+                // Skip over next byte to align the PC with 4 byte.
+                vm.next_8_bits();
+            }
+            Opcode::FLAGLOG => {
+                let i = vm.next_8_bits() as usize;
+                vm.skip_bytes(2);
+                let history = vm.get_register(i).wrapping_shl(1) | i32::from(vm.equal_flag);
+                vm.write_register(i, history);
+            }
+            Opcode::SELECT => {
+                let dst = vm.next_8_bits() as usize;
+                let a = vm.read_register();
+                let b = vm.read_register();
+                vm.write_register(dst, if vm.equal_flag { a } else { b });
+            }
+            Opcode::SETZ => {
+                let i = vm.next_8_bits() as usize;
+                vm.write_register(i, i32::from(vm.equal_flag));
+                vm.skip_bytes(2);
+            }
+            Opcode::SETNZ => {
+                let i = vm.next_8_bits() as usize;
+                vm.write_register(i, i32::from(!vm.equal_flag));
+                vm.skip_bytes(2);
+            }
+            _ => return Err(VMError::IllegalOpcode(opcode as u8)),
+        }
+        Ok(())
+    }
+
+    fn memory(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError> {
+        match opcode {
+            Opcode::LOAD => {
+                // Load is of the form:
+                // LOAD #register, operand
+
+                let reg = vm.next_8_bits() as usize;
+                let num = vm.next_16_bits();
+                vm.write_register(reg, i32::from(num));
+            }
+            Opcode::LOADW => {
+                let reg = vm.next_8_bits() as usize;
+                let num = vm.next_32_bits();
+                vm.write_register(reg, num as i32);
+
+                // Skip over the padding at the end of the instruction.
+                vm.skip_bytes(2);
+            }
+            Opcode::MOV => {
+                let dst = vm.next_8_bits() as usize;
+                let value = vm.read_register();
+                vm.write_register(dst, value);
+                vm.skip_bytes(1);
+            }
+            Opcode::ALOC => {
+                let size = vm.read_register();
+                vm.skip_bytes(2);
+                if size < 0 {
+                    return Err(VMError::NegativeAllocation(size));
+                }
+                let new_size = vm.heap.len().saturating_add(size as usize);
+                if new_size > vm.max_heap {
+                    return Err(VMError::HeapLimitExceeded(new_size));
+                }
+                vm.heap.resize(new_size, vm.heap_fill);
+            }
+            Opcode::RMOV => {
+                let dst = vm.next_8_bits() as usize;
+                let src = vm.next_8_bits() as usize;
+                let count = vm.read_register() as usize;
+                for &base in &[dst, src] {
+                    if count > MAX_REGISTERS || base + count > MAX_REGISTERS {
+                        return Err(VMError::InvalidRegisterRange { base, count });
+                    }
+                }
+                vm.registers.copy_within(src..src + count, dst);
+                for i in dst..dst + count {
+                    vm.written |= 1 << i;
+                }
+            }
+            Opcode::LOADM => {
+                let dst = vm.next_8_bits() as usize;
+                let addr = vm.read_register() as usize;
+                vm.skip_bytes(1);
+
+                let word = match vm.heap.get(addr..addr.saturating_add(4)) {
+                    Some(word) if word.len() == 4 => word,
+                    _ => return Err(VMError::InvalidHeapAddress(addr)),
+                };
+                let value = i32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+                vm.write_register(dst, value);
+            }
+            Opcode::STOREM => {
+                let addr = vm.read_register() as usize;
+                let value = vm.read_register();
+                vm.skip_bytes(1);
+
+                match vm.heap.get_mut(addr..addr.saturating_add(4)) {
+                    Some(word) if word.len() == 4 => word.copy_from_slice(&value.to_be_bytes()),
+                    _ => return Err(VMError::InvalidHeapAddress(addr)),
+                }
+            }
+            Opcode::LMW | Opcode::SMW => {
+                let store = opcode == Opcode::SMW;
+                let base = vm.next_8_bits() as usize;
+                let addr = vm.read_register() as usize;
+                let count = vm.read_register() as usize;
+                if count > MAX_REGISTERS || base + count > MAX_REGISTERS {
+                    return Err(VMError::InvalidRegisterRange { base, count });
+                }
+                match addr.checked_add(count * 4) {
+                    Some(end) if end <= vm.heap.len() => {}
+                    _ => return Err(VMError::InvalidHeapAddress(addr)),
+                }
+
+                for i in 0..count {
+                    let word = addr + i * 4;
+                    if store {
+                        let value = vm.get_register(base + i);
+                        vm.heap[word..word + 4].copy_from_slice(&value.to_be_bytes());
+                    } else {
+                        let bytes = [
+                            vm.heap[word],
+                            vm.heap[word + 1],
+                            vm.heap[word + 2],
+                            vm.heap[word + 3],
+                        ];
+                        vm.write_register(base + i, i32::from_be_bytes(bytes));
+                    }
+                }
+            }
+            Opcode::AMOADD => {
+                let dst = vm.next_8_bits() as usize;
+                let addr = vm.read_register() as usize;
+                let value = vm.read_register();
+
+                let heap = match &vm.shared_heap {
+                    Some(heap) => heap,
+                    None => return Err(VMError::InvalidSharedAddress(addr)),
+                };
+                // Words are written in one go, so a VM that panicked while
+                // holding the lock can't have left the heap inconsistent.
+                let mut heap = heap.lock().unwrap_or_else(|e| e.into_inner());
+                let word = match heap.get_mut(addr..addr.saturating_add(4)) {
+                    Some(word) if word.len() == 4 => word,
+                    _ => return Err(VMError::InvalidSharedAddress(addr)),
+                };
+
+                let old = i32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+                word.copy_from_slice(&old.wrapping_add(value).to_be_bytes());
+                drop(heap);
+                vm.write_register(dst, old);
+            }
+            Opcode::INARG => {
+                let dst = vm.next_8_bits() as usize;
+                let index = vm.read_register();
+                vm.skip_bytes(1);
+
+                // Elements are read through the heap mapping of the input. A
+                // negative index wraps to the address below the buffer.
+                let addr = INPUT_BUFFER_BASE.wrapping_add((index as usize).wrapping_mul(4));
+                if index < 0 || index as usize >= vm.input.len() / 4 {
+                    return Err(VMError::InvalidHeapAddress(addr));
+                }
+                let element = match vm.heap.get(addr..addr + 4) {
+                    Some(element) => element,
+                    None => return Err(VMError::InvalidHeapAddress(addr)),
+                };
+                let value = i32::from_be_bytes([element[0], element[1], element[2], element[3]]);
+                vm.write_register(dst, value);
+            }
+            Opcode::XORRANGE => {
+                let dst = vm.next_8_bits() as usize;
+                let addr = vm.read_register() as usize;
+                let len = vm.read_register() as usize;
+                let end = addr.checked_add(len);
+                let folded = match end.and_then(|end| vm.heap.get(addr..end)) {
+                    Some(region) => region.iter().fold(0, |acc, &byte| acc ^ byte),
+                    None => return Err(VMError::InvalidHeapAddress(addr)),
+                };
+                vm.write_register(dst, i32::from(folded));
+            }
+            Opcode::BZERO => {
+                let addr = vm.read_register() as usize;
+                let len = vm.read_register() as usize;
+                let end = addr.checked_add(len);
+                match end.and_then(|end| vm.heap.get_mut(addr..end)) {
+                    Some(region) => region.fill(0),
+                    None => return Err(VMError::InvalidHeapAddress(addr)),
+                }
+                vm.skip_bytes(1);
+            }
+            Opcode::XCHG => {
+                let reg = vm.next_8_bits() as usize;
+                let addr = vm.read_register() as usize;
+                let value = vm.get_register(reg);
+                vm.skip_bytes(1);
+
+                let heap = match &vm.shared_heap {
+                    Some(heap) => heap,
+                    None => return Err(VMError::InvalidSharedAddress(addr)),
+                };
+                // Like AMOADD, a poisoned lock still guards a consistent heap.
+                let mut heap = heap.lock().unwrap_or_else(|e| e.into_inner());
+                let word = match heap.get_mut(addr..addr.saturating_add(4)) {
+                    Some(word) if word.len() == 4 => word,
+                    _ => return Err(VMError::InvalidSharedAddress(addr)),
+                };
+
+                let old = i32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+                word.copy_from_slice(&value.to_be_bytes());
+                drop(heap);
+                vm.write_register(reg, old);
+            }
+            Opcode::LPROG => {
+                let dst = vm.next_8_bits() as usize;
+                let addr = vm.read_register() as usize;
+                match vm.program.get(addr) {
+                    Some(&byte) => vm.write_register(dst, i32::from(byte)),
+                    None => return Err(VMError::InvalidProgramAddress(addr)),
+                }
+
+                // Skip over next byte to align the PC with 4 byte.
+                vm.next_8_bits();
+            }
+            Opcode::SPROG => {
+                // NOTE: Patching the program changes instructions in place.
+                // Patching the instruction that is currently executing or
+                // writing a byte that isn't an opcode/operand boundary will
+                // happily produce garbage. Anything that caches decoded
+                // instructions must be invalidated here.
+                let value = vm.read_register();
+                let addr = vm.read_register() as usize;
+                match vm.program.get_mut(addr) {
+                    Some(byte) => *byte = value as u8,
+                    None => return Err(VMError::InvalidProgramAddress(addr)),
+                }
+
+                // Skip over next byte to align the PC with 4 byte.
+                vm.next_8_bits();
+            }
+            _ => return Err(VMError::IllegalOpcode(opcode as u8)),
+        }
+        Ok(())
+    }
+
+    fn stack(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError> {
+        match opcode {
+            Opcode::SP => {
+                let i = vm.next_8_bits() as usize;
+                vm.write_register(i, vm.stack.len() as i32);
+                vm.skip_bytes(2);
+            }
+            Opcode::PUSH => {
+                let value = vm.read_register();
+                vm.stack.push(value);
+                vm.skip_bytes(2);
+            }
+            Opcode::POP => {
+                let i = vm.next_8_bits() as usize;
+                match vm.stack.pop() {
+                    Some(value) => vm.write_register(i, value),
+                    None => return Err(VMError::StackUnderflow),
+                }
+                vm.skip_bytes(2);
+            }
+            _ => return Err(VMError::IllegalOpcode(opcode as u8)),
+        }
+        Ok(())
+    }
+
+    fn system(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError> {
+        match opcode {
+            Opcode::SYS => {
+                // Syscalls are numbered with a byte, so wider numbers can't
+                // name one.
+                let num = vm.next_16_bits();
+                let handler = match vm.syscalls.get(&(num as u8)) {
+                    Some(handler) if num <= u16::from(u8::MAX) => *handler,
+                    _ => return Err(VMError::UnknownSyscall(num)),
+                };
+                handler(vm);
+
+                // Skip over next byte to align the PC with 4 byte.
+                vm.next_8_bits();
+            }
+            Opcode::CLOCK => {
+                let i = vm.next_8_bits() as usize;
+                let elapsed = (vm.clock)(vm.started).as_millis();
+                vm.write_register(i, elapsed.min(i32::MAX as u128) as i32);
+                vm.skip_bytes(2);
+            }
+            _ => return Err(VMError::IllegalOpcode(opcode as u8)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::assembler::Assembler;
+
+    // Counts the executed ADD instructions and leaves the rest to the
+    // interpreter.
+    #[derive(Debug)]
+    struct CountingExecutor {
+        adds: Arc<AtomicUsize>,
+    }
+
+    impl Executor for CountingExecutor {
+        fn control(&mut self, vm: &mut VM, opcode: Opcode) -> Result<Option<HaltReason>, VMError> {
+            InterpreterExecutor.control(vm, opcode)
+        }
+
+        fn arithmetic(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError> {
+            if opcode == Opcode::ADD {
+                self.adds.fetch_add(1, Ordering::SeqCst);
+            }
+            InterpreterExecutor.arithmetic(vm, opcode)
+        }
+
+        fn comparison(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError> {
+            InterpreterExecutor.comparison(vm, opcode)
+        }
+
+        fn memory(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError> {
+            InterpreterExecutor.memory(vm, opcode)
+        }
+
+        fn stack(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError> {
+            InterpreterExecutor.stack(vm, opcode)
+        }
+
+        fn system(&mut self, vm: &mut VM, opcode: Opcode) -> Result<(), VMError> {
+            InterpreterExecutor.system(vm, opcode)
+        }
+    }
+
+    #[test]
+    fn test_custom_executor() {
+        let program = Assembler::new()
+            .assemble("load $0 #7\nload $1 #5\nadd $0 $1 $2\nmul $2 $1 $3\nadd $3 $2 $4\nhlt")
+            .unwrap();
+
+        let mut expected = VM::new();
+        expected.add_bytes(&program);
+        expected.run().unwrap();
+
+        let adds = Arc::new(AtomicUsize::new(0));
+        let mut vm = VM::new();
+        vm.set_executor(Box::new(CountingExecutor { adds: adds.clone() }));
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+
+        assert_eq!(adds.load(Ordering::SeqCst), 2);
+        assert_eq!(vm.register(4), 72);
+        assert_eq!(
+            vm.registers().collect::<Vec<_>>(),
+            expected.registers().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_interpreter_rejects_other_groups() {
+        let mut vm = VM::new();
+        vm.add_bytes(&[0, 0, 0, 0]);
+        assert_eq!(
+            InterpreterExecutor.arithmetic(&mut vm, Opcode::JMP),
+            Err(VMError::IllegalOpcode(Opcode::JMP as u8))
+        );
+    }
+}
@@ -0,0 +1,3274 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::assembler::header::BinaryHeader;
+use crate::assembler::symbols::parse_symbol_map;
+use crate::assembler::{BIN_HEADER_LENGTH, BIN_VERSION};
+use crate::disassembler::{operand_kinds, OperandKind};
+use crate::opcode::Opcode;
+
+mod executor;
+
+pub use self::executor::{Executor, InterpreterExecutor};
+
+/// Max number of logical registers in the VM.
+pub const MAX_REGISTERS: usize = 32;
+
+/// Default limit on the number of nested subroutine calls.
+const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// Syscall that prints the integer in $0 to stdout.
+pub const SYSCALL_PRINT_INT: u8 = 0;
+
+/// Syscall that reads an integer from stdin into $0.
+pub const SYSCALL_READ_INT: u8 = 1;
+
+/// Number of instructions between calls to the progress callback of
+/// run_steps_reporting.
+pub const PROGRESS_INTERVAL: usize = 1000;
+
+/// Heap address at which the input buffer is mapped. Data sections that are
+/// larger than this overlap with the input.
+pub const INPUT_BUFFER_BASE: usize = 0x10000;
+
+/// Handler invoked by the SYS instruction. It gets access to the whole VM so
+/// it can read and write registers and the heap.
+pub type Syscall = fn(&mut VM);
+
+/// Source of the elapsed time reported by CLOCK. It gets the instant at which
+/// the VM was created.
+pub type Clock = fn(Instant) -> Duration;
+
+/// Heap memory shared by multiple VMs. Instructions that access it hold the
+/// lock for their whole duration so they are atomic with respect to each
+/// other.
+pub type SharedHeap = Arc<Mutex<Vec<u8>>>;
+
+/// Reasons for the VM to stop executing a program normally.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HaltReason {
+    /// HLT instruction was executed.
+    Halted,
+
+    /// HALTR instruction was executed. Registers hold the program's result.
+    HaltedWithResult,
+
+    /// Program counter ran past the end of the program.
+    EndOfProgram,
+}
+
+/// Callback invoked when the VM stops executing a program normally.
+pub type HaltHook = Box<dyn FnMut(HaltReason) + Send>;
+
+// Wraps the halt hook so that VM can still derive Debug.
+struct HaltHookSlot(HaltHook);
+
+impl fmt::Debug for HaltHookSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HaltHook")
+    }
+}
+
+/// Errors that abort the execution of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VMError {
+    /// Program doesn't start with a valid executable header.
+    InvalidHeader,
+
+    /// Program was produced for another version of the executable format.
+    /// Holds the version found in the header.
+    UnsupportedVersion(u8),
+
+    /// Encountered a byte that doesn't decode to a known opcode.
+    IllegalOpcode(u8),
+
+    /// CALL exceeded the maximum call depth.
+    StackOverflow,
+
+    /// RET was executed with an empty call stack.
+    StackUnderflow,
+
+    /// Program was accessed outside of its bounds.
+    InvalidProgramAddress(usize),
+
+    /// A range of registers extends beyond the last register.
+    InvalidRegisterRange { base: usize, count: usize },
+
+    /// An instruction referenced a register that doesn't exist.
+    InvalidRegister(usize),
+
+    /// SYS was executed with a number that has no registered handler.
+    UnknownSyscall(u16),
+
+    /// Integer division with a zero divisor.
+    DivisionByZero,
+
+    /// An instruction read a register that was never written. Only reported
+    /// in strict mode.
+    UninitializedRegister(usize),
+
+    /// Shared heap was accessed outside of its bounds or isn't attached.
+    InvalidSharedAddress(usize),
+
+    /// Heap was accessed outside of its bounds.
+    InvalidHeapAddress(usize),
+
+    /// Heap would grow beyond its limit. Holds the requested size.
+    HeapLimitExceeded(usize),
+
+    /// ALOC was asked to allocate a negative number of bytes.
+    NegativeAllocation(i32),
+
+    /// Program was still running after executing the maximum number of
+    /// instructions it was given. Holds that number.
+    BudgetExceeded(u64),
+}
+
+impl fmt::Display for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VMError::InvalidHeader => write!(f, "invalid binary header"),
+            VMError::UnsupportedVersion(version) => {
+                write!(f, "unsupported executable version {}", version)
+            }
+            VMError::IllegalOpcode(op) => write!(f, "illegal opcode {}", op),
+            VMError::StackOverflow => write!(f, "call stack overflow"),
+            VMError::StackUnderflow => write!(f, "stack underflow"),
+            VMError::InvalidProgramAddress(addr) => {
+                write!(f, "program address {} is out of bounds", addr)
+            }
+            VMError::InvalidRegisterRange { base, count } => write!(
+                f,
+                "register range ${}..${} is out of bounds",
+                base,
+                base.saturating_add(*count)
+            ),
+            VMError::InvalidRegister(reg) => write!(f, "register ${} is out of bounds", reg),
+            VMError::UnknownSyscall(num) => write!(f, "unknown syscall {}", num),
+            VMError::DivisionByZero => write!(f, "division by zero"),
+            VMError::UninitializedRegister(reg) => {
+                write!(f, "register ${} is read before being written", reg)
+            }
+            VMError::InvalidSharedAddress(addr) => {
+                write!(f, "shared heap address {} is out of bounds", addr)
+            }
+            VMError::InvalidHeapAddress(addr) => {
+                write!(f, "heap address {} is out of bounds", addr)
+            }
+            VMError::HeapLimitExceeded(size) => {
+                write!(f, "heap size {} exceeds the limit", size)
+            }
+            VMError::NegativeAllocation(size) => {
+                write!(f, "can't allocate a negative size {}", size)
+            }
+            VMError::BudgetExceeded(budget) => {
+                write!(f, "still running after {} instructions", budget)
+            }
+        }
+    }
+}
+
+impl Error for VMError {}
+
+/// Builds a VM with preloaded state. Unlike poking at a VM directly, the
+/// state is validated when the VM is built.
+#[derive(Debug, Default)]
+pub struct VmBuilder {
+    registers: Vec<(usize, i32)>,
+    heap: Vec<u8>,
+    program: Vec<u8>,
+    max_heap: Option<usize>,
+}
+
+impl VmBuilder {
+    /// Preload register `i` with the value.
+    pub fn register(mut self, i: usize, value: i32) -> Self {
+        self.registers.push((i, value));
+        self
+    }
+
+    /// Initial contents of the heap.
+    pub fn heap(mut self, bytes: Vec<u8>) -> Self {
+        self.heap = bytes;
+        self
+    }
+
+    /// Bytecode of the program including its header.
+    pub fn program(mut self, bytes: Vec<u8>) -> Self {
+        self.program = bytes;
+        self
+    }
+
+    /// Limit the size of the heap. ALOC fails with a HeapLimitExceeded error
+    /// once the heap would grow beyond it.
+    pub fn max_heap(mut self, bytes: usize) -> Self {
+        self.max_heap = Some(bytes);
+        self
+    }
+
+    /// Builds the VM. Fails if a register doesn't exist or the heap is
+    /// already larger than its limit.
+    pub fn build(self) -> Result<VM, VMError> {
+        let mut vm = VM::new();
+        for (i, value) in self.registers {
+            if i >= MAX_REGISTERS {
+                return Err(VMError::InvalidRegister(i));
+            }
+            vm.write_register(i, value);
+        }
+
+        if let Some(max_heap) = self.max_heap {
+            if self.heap.len() > max_heap {
+                return Err(VMError::HeapLimitExceeded(self.heap.len()));
+            }
+            vm.max_heap = max_heap;
+        }
+        vm.heap = self.heap;
+        vm.program = self.program;
+        Ok(vm)
+    }
+}
+
+/// Main structure that holds all the state of the Iridium VM.
+#[derive(Debug)]
+pub struct VM {
+    // Logical registers.
+    registers: [i32; MAX_REGISTERS],
+
+    // Program counter that tracks which instruction is to be executed next.
+    pc: usize,
+
+    // Bytecode of the program. Instructions are decoded straight from it on
+    // every step, so there is no decoded-instruction cache to invalidate when
+    // add_bytes or SPROG modify it.
+    program: Vec<u8>,
+
+    // Tracks the remainder of the integer division operation.
+    remainder: u32,
+
+    // Tracks the result of the last comparison operation.
+    equal_flag: bool,
+
+    // Set if the last arithmetic operation overflowed.
+    overflow_flag: bool,
+
+    // Heap for dynamic memory allocation.
+    heap: Vec<u8>,
+
+    // Byte used to fill newly allocated heap memory.
+    heap_fill: u8,
+
+    // Return addresses of the active subroutine calls.
+    call_stack: Vec<usize>,
+
+    // Max number of nested subroutine calls.
+    max_call_depth: usize,
+
+    // Operand stack used for expression evaluation.
+    stack: Vec<i32>,
+
+    // Set once the program halts through HALTR.
+    has_result: bool,
+
+    // Handlers for the SYS instruction indexed by syscall number.
+    syscalls: HashMap<u8, Syscall>,
+
+    // Registers pinned to a fixed value along with that value.
+    readonly: Vec<(usize, i32)>,
+
+    // Instant at which the VM was created.
+    started: Instant,
+
+    // Measures the time elapsed since the VM was created.
+    clock: Clock,
+
+    // Buffer that collects the program's output instead of stdout if set.
+    captured_output: Option<String>,
+
+    // Trap reads of registers that were never written.
+    strict_registers: bool,
+
+    // Bitmask of the registers that have been written.
+    written: u32,
+
+    // First nonexistent register accessed by the executing instruction.
+    invalid_register: Option<usize>,
+
+    // Heap memory shared with other VMs, if any.
+    shared_heap: Option<SharedHeap>,
+
+    // Most recently executed instructions, oldest first.
+    pc_history: VecDeque<(usize, Opcode)>,
+
+    // Max number of entries kept in pc_history. 0 disables it.
+    pc_history_len: usize,
+
+    // Names of code addresses loaded from a symbol map. They are only used
+    // to annotate debugging output.
+    symbols: HashMap<String, usize>,
+
+    // Max size of the heap in bytes.
+    max_heap: usize,
+
+    // Input of the program. It's mapped into the heap at INPUT_BUFFER_BASE.
+    input: Vec<u8>,
+
+    // Invoked whenever the program halts.
+    halt_hook: Option<HaltHookSlot>,
+
+    // Executes the decoded instructions. The built-in interpreter is used
+    // if it isn't set.
+    executor: Option<Box<dyn Executor>>,
+
+    // Code addresses at which run_until_breakpoint pauses.
+    breakpoints: Vec<usize>,
+
+    // Number of instructions executed so far.
+    cycles: u64,
+}
+
+impl VM {
+    /// Create a new VM instance.
+    pub fn new() -> Self {
+        let mut syscalls: HashMap<u8, Syscall> = HashMap::new();
+        syscalls.insert(SYSCALL_PRINT_INT, sys_print_int);
+        syscalls.insert(SYSCALL_READ_INT, sys_read_int);
+
+        VM {
+            registers: [0; MAX_REGISTERS],
+            pc: 0,
+            program: vec![],
+            remainder: 0,
+            equal_flag: false,
+            overflow_flag: false,
+            heap: vec![],
+            heap_fill: 0,
+            call_stack: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            stack: vec![],
+            has_result: false,
+            syscalls,
+            readonly: vec![],
+            started: Instant::now(),
+            clock: |started| started.elapsed(),
+            captured_output: None,
+            strict_registers: false,
+            written: 0,
+            invalid_register: None,
+            shared_heap: None,
+            pc_history: VecDeque::new(),
+            pc_history_len: 0,
+            symbols: HashMap::new(),
+            max_heap: usize::MAX,
+            input: vec![],
+            halt_hook: None,
+            executor: None,
+            breakpoints: vec![],
+            cycles: 0,
+        }
+    }
+
+    /// Start building a VM with preloaded registers, heap and program.
+    pub fn builder() -> VmBuilder {
+        VmBuilder::default()
+    }
+
+    /// Set a callback that is invoked once every time the program halts,
+    /// whether it's run to completion or stepped through. It isn't invoked
+    /// if execution is aborted by an error.
+    pub fn set_halt_hook(&mut self, hook: HaltHook) {
+        self.halt_hook = Some(HaltHookSlot(hook));
+    }
+
+    /// Execute instructions with the specified executor instead of the
+    /// built-in interpreter.
+    pub fn set_executor(&mut self, executor: Box<dyn Executor>) {
+        self.executor = Some(executor);
+    }
+
+    /// Register a handler for the SYS instruction. Replaces any existing
+    /// handler for the same number, including the built-in ones.
+    pub fn register_syscall(&mut self, num: u8, handler: Syscall) {
+        self.syscalls.insert(num, handler);
+    }
+
+    /// Limit the number of nested subroutine calls. CALL fails with a
+    /// StackOverflow error once the limit is reached.
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    /// Make instructions that read a register that was never written fail
+    /// with an UninitializedRegister error and have no effect. It's off by
+    /// default.
+    pub fn with_strict_registers(mut self, strict: bool) -> Self {
+        self.strict_registers = strict;
+        self
+    }
+
+    /// Attach heap memory that is shared with other VMs.
+    pub fn with_shared_heap(mut self, heap: SharedHeap) -> Self {
+        self.shared_heap = Some(heap);
+        self
+    }
+
+    /// Remember the address and opcode of the last `len` executed
+    /// instructions. This helps figuring out how a program ended up
+    /// trapping. It's disabled by default.
+    pub fn with_pc_history(mut self, len: usize) -> Self {
+        self.pc_history_len = len;
+        self
+    }
+
+    /// Address and opcode of the most recently executed instructions, oldest
+    /// first. Empty unless enabled through with_pc_history().
+    pub fn pc_history(&self) -> Vec<(usize, Opcode)> {
+        self.pc_history.iter().copied().collect()
+    }
+
+    /// Loads an executable written by Assembler::assemble_to_file. It's
+    /// installed like load_program does and an invalid header is reported
+    /// as InvalidData.
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+        self.load_program(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Loads a symbol map written by Assembler::write_symbol_map so that
+    /// addresses can be shown along with their names.
+    pub fn load_symbols<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let map = fs::read_to_string(path)?;
+        self.symbols = parse_symbol_map(&map).map_err(|line| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed symbol map at line {}", line),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Provide input to the program. The buffer is mapped into the heap at
+    /// INPUT_BUFFER_BASE and its 32-bit elements can be read with INARG.
+    /// Fails if mapping it would grow the heap beyond its limit.
+    pub fn set_input_buffer(&mut self, input: Vec<u8>) -> Result<(), VMError> {
+        let end = INPUT_BUFFER_BASE.saturating_add(input.len());
+        if end > self.max_heap {
+            return Err(VMError::HeapLimitExceeded(end));
+        }
+        self.input = input;
+        self.map_input();
+        Ok(())
+    }
+
+    // Copies the input buffer into the heap, growing it if needed.
+    fn map_input(&mut self) {
+        if self.input.is_empty() {
+            return;
+        }
+        let end = INPUT_BUFFER_BASE + self.input.len();
+        if self.heap.len() < end {
+            self.heap.resize(end, self.heap_fill);
+        }
+        self.heap[INPUT_BUFFER_BASE..end].copy_from_slice(&self.input);
+    }
+
+    /// Symbols loaded from a symbol map.
+    pub fn symbols(&self) -> &HashMap<String, usize> {
+        &self.symbols
+    }
+
+    // Formats the address along with its name if there is one i.e. 0044 <loop>.
+    fn describe_address(&self, addr: usize) -> String {
+        match self.symbols.iter().find(|(_, &offset)| offset == addr) {
+            Some((name, _)) => format!("{:04x} <{}>", addr, name),
+            None => format!("{:04x}", addr),
+        }
+    }
+
+    /// Replace the source of the time reported by CLOCK. Mainly useful to
+    /// make programs that read the clock deterministic.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Collect the program's output in memory instead of writing it to
+    /// stdout. It can be retrieved with take_output().
+    pub fn capture_output(&mut self) {
+        self.captured_output = Some(String::new());
+    }
+
+    /// Returns the output collected so far and clears it. It's empty unless
+    /// capture_output() was called.
+    pub fn take_output(&mut self) -> String {
+        match &mut self.captured_output {
+            Some(output) => std::mem::take(output),
+            None => String::new(),
+        }
+    }
+
+    /// Write program output. Syscalls should use it instead of printing
+    /// directly so that the output can be captured.
+    pub fn write_output(&mut self, s: &str) {
+        match &mut self.captured_output {
+            Some(output) => output.push_str(s),
+            None => print!("{}", s),
+        }
+    }
+
+    /// Dump VM state on terminal.
+    pub fn dump_state(&self) {
+        // Not dumping the registers are they are exposed through
+        // the registers() iterator and can be examined as needed.
+        println!("VM state snapshot:\n------------------");
+        println!("\tPC: {}", self.describe_address(self.pc));
+        println!("\tCycles: {}", self.cycles);
+        println!("\tEqual Flag: {}", self.equal_flag);
+        println!("\tOverflow Flag: {}", self.overflow_flag);
+        println!("\tRemainder: {}", self.remainder);
+        println!("\tHeap Length: {}", self.heap.len());
+        println!("\tCall Stack: {:?}", self.call_stack);
+        println!("\tStack: {:?}", self.stack);
+        println!("\tProgram: {:?}", self.program);
+        if self.pc_history_len > 0 {
+            println!("\tRecent Instructions:");
+            for (pc, opcode) in &self.pc_history {
+                println!("\t\t{}: {:?}", self.describe_address(*pc), opcode);
+            }
+        }
+    }
+
+    /// Execute the VM instance to completion.
+    pub fn run(&mut self) -> Result<HaltReason, VMError> {
+        self.run_with_budget(None)
+    }
+
+    /// Execute the VM instance to completion, giving up with a
+    /// BudgetExceeded error if it's still running after `budget`
+    /// instructions. There is no limit if it's None.
+    pub fn run_with_budget(&mut self, budget: Option<u64>) -> Result<HaltReason, VMError> {
+        match self.run_until(budget, false)? {
+            Some(reason) => Ok(reason),
+            None => unreachable!("breakpoints are ignored"),
+        }
+    }
+
+    /// Execute at most `n` instructions. The progress callback is invoked
+    /// with the number of instructions executed so far after every
+    /// PROGRESS_INTERVAL of them, so hosts can show progress without
+    /// blocking indefinitely. Returns None if the program is still running
+    /// once the budget is used up.
+    pub fn run_steps_reporting(
+        &mut self,
+        n: usize,
+        mut progress: impl FnMut(usize),
+    ) -> Result<Option<HaltReason>, VMError> {
+        self.start()?;
+        for step in 1..=n {
+            if let Some(reason) = self.execute_instruction()? {
+                return Ok(Some(reason));
+            }
+            if step % PROGRESS_INTERVAL == 0 {
+                progress(step);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Replace the program with the specified executable. The header is
+    /// validated and execution is set up to start at the first instruction.
+    /// The header stays in program memory so that the addresses recorded by
+    /// the assembler remain valid.
+    pub fn load_program(&mut self, bytes: &[u8]) -> Result<(), VMError> {
+        let header = parse_header(bytes)?;
+        self.program = bytes.to_vec();
+        self.load_data(&header)?;
+        self.pc = header.entry_point();
+        Ok(())
+    }
+
+    // Validates the header. If this is the initial execution, it also sets
+    // the program counter past the header and loads the data section.
+    fn start(&mut self) -> Result<(), VMError> {
+        let header = parse_header(&self.program)?;
+
+        if self.pc == 0 {
+            self.load_data(&header)?;
+            self.pc = header.entry_point();
+        }
+        Ok(())
+    }
+
+    // Copies the data section of the program to the start of the heap so the
+    // offsets of data symbols become heap addresses. The rest of the heap is
+    // left as it is.
+    fn load_data(&mut self, header: &BinaryHeader) -> Result<(), VMError> {
+        let start = header.data_start as usize;
+        if start == 0 || start > self.program.len() {
+            return Ok(());
+        }
+        let data = &self.program[start..];
+        if data.len() > self.max_heap {
+            return Err(VMError::HeapLimitExceeded(data.len()));
+        }
+        if self.heap.len() < data.len() {
+            self.heap.resize(data.len(), self.heap_fill);
+        }
+        self.heap[..data.len()].copy_from_slice(data);
+        self.map_input();
+        Ok(())
+    }
+
+    /// Execute one instruction. Returns the reason if the program has
+    /// terminated or None if there is more to execute.
+    pub fn run_once(&mut self) -> Result<Option<HaltReason>, VMError> {
+        self.start()?;
+        self.execute_instruction()
+    }
+
+    /// Execute one instruction, stepping over subroutine calls. If the next
+    /// instruction is a CALL or CALLI, execution continues until the subroutine
+    /// returns i.e. the call stack is back to its current depth. Otherwise
+    /// it behaves exactly like run_once(). Like run_with_budget, a budget
+    /// limits the number of instructions executed.
+    pub fn step_over(&mut self, budget: Option<u64>) -> Result<Option<HaltReason>, VMError> {
+        self.start()?;
+        let is_call = self.pc < self.program.len()
+            && [Opcode::CALL, Opcode::CALLI].contains(&Opcode::from(self.program[self.pc]));
+
+        let limit = budget.map(|budget| self.cycles.saturating_add(budget));
+        let depth = self.call_stack.len();
+        let mut result = self.execute_instruction();
+        if is_call {
+            while result == Ok(None) && self.call_stack.len() > depth {
+                if let (Some(budget), Some(limit)) = (budget, limit) {
+                    if self.cycles >= limit {
+                        return Err(VMError::BudgetExceeded(budget));
+                    }
+                }
+                result = self.execute_instruction();
+            }
+        }
+        result
+    }
+
+    /// Run the program until the PC reaches a breakpoint or it stops. The
+    /// instruction at the current PC is always executed so a paused program
+    /// can be resumed. Returns None when paused at a breakpoint; `pc()` is
+    /// then the breakpoint's offset. The budget works like in run_with_budget().
+    pub fn run_until_breakpoint(
+        &mut self,
+        budget: Option<u64>,
+    ) -> Result<Option<HaltReason>, VMError> {
+        self.run_until(budget, true)
+    }
+
+    fn run_until(
+        &mut self,
+        budget: Option<u64>,
+        pause_at_breakpoints: bool,
+    ) -> Result<Option<HaltReason>, VMError> {
+        self.start()?;
+        let limit = budget.map(|budget| self.cycles.saturating_add(budget));
+        loop {
+            if let Some(reason) = self.execute_instruction()? {
+                return Ok(Some(reason));
+            }
+            if pause_at_breakpoints && self.breakpoints.contains(&self.pc) {
+                return Ok(None);
+            }
+            if let (Some(budget), Some(limit)) = (budget, limit) {
+                if self.cycles >= limit {
+                    return Err(VMError::BudgetExceeded(budget));
+                }
+            }
+        }
+    }
+
+    /// Pause run_until_breakpoint before executing the instruction at `pc`.
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        if !self.breakpoints.contains(&pc) {
+            self.breakpoints.push(pc);
+        }
+    }
+
+    /// Code addresses at which run_until_breakpoint pauses.
+    pub fn breakpoints(&self) -> &[usize] {
+        &self.breakpoints
+    }
+
+    /// Reset the flags and the remainder to their defaults. Registers and the
+    /// program are left untouched.
+    pub fn clear_flags(&mut self) {
+        self.equal_flag = false;
+        self.overflow_flag = false;
+        self.remainder = 0;
+    }
+
+    /// Set the byte that ALOC uses to fill newly allocated heap memory.
+    /// It defaults to 0.
+    pub fn set_heap_fill(&mut self, byte: u8) {
+        self.heap_fill = byte;
+    }
+
+    /// Produce a classic offset/hex/ASCII dump of a heap slice. The range is
+    /// clamped to the bounds of the heap.
+    pub fn hexdump_heap(&self, start: usize, len: usize) -> String {
+        let start = start.min(self.heap.len());
+        let end = start.saturating_add(len).min(self.heap.len());
+
+        let mut dump = String::new();
+        for (i, chunk) in self.heap[start..end].chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            dump.push_str(&format!(
+                "{:08x}  {:<47}  |{}|\n",
+                start + i * 16,
+                hex.join(" "),
+                ascii
+            ));
+        }
+        dump
+    }
+
+    /// Bytecode of the loaded program including its header.
+    pub fn program(&self) -> &[u8] {
+        &self.program
+    }
+
+    /// Address of the next instruction to execute.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Number of instructions executed so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Current depth of the call stack.
+    pub fn call_depth(&self) -> usize {
+        self.call_stack.len()
+    }
+
+    /// Append a bytecode to VM's program.
+    pub fn add_byte(&mut self, v: u8) {
+        self.program.push(v);
+    }
+
+    /// Append raw bytecode to VM's program.
+    pub fn add_bytes(&mut self, v: &[u8]) {
+        self.program.extend_from_slice(v);
+    }
+
+    /// Append instructions to the end of the code section. An empty program
+    /// gets a header first and a data section is moved after the new code.
+    pub fn append_code(&mut self, code: &[u8]) -> Result<(), VMError> {
+        if self.program.is_empty() {
+            self.program
+                .extend_from_slice(&BinaryHeader::new().to_bytes());
+        }
+        let mut header = parse_header(&self.program)?;
+        if header.data_start == 0 {
+            self.program.extend_from_slice(code);
+            return Ok(());
+        }
+
+        let end = header.data_start as usize;
+        self.program.splice(end..end, code.iter().cloned());
+        header.data_start += code.len() as u32;
+        self.program[..BIN_HEADER_LENGTH].copy_from_slice(&header.to_bytes());
+        Ok(())
+    }
+
+    /// Returns the final registers if the program halted through HALTR.
+    /// Programs that stopped any other way haven't produced a result.
+    pub fn result_registers(&self) -> Option<Vec<i32>> {
+        if self.has_result {
+            Some(self.registers.to_vec())
+        } else {
+            None
+        }
+    }
+
+    /// Read a register's value.
+    pub fn register(&self, i: usize) -> i32 {
+        return self.registers[i];
+    }
+
+    /// Write a register's value. Fails if the register doesn't exist.
+    pub fn set_register(&mut self, i: usize, value: i32) -> Result<(), VMError> {
+        if i >= MAX_REGISTERS {
+            return Err(VMError::InvalidRegister(i));
+        }
+        self.write_register(i, value);
+        Ok(())
+    }
+
+    /// Pin a register to a fixed value. Instructions that write to the
+    /// register are still executed but the register keeps its value. Fails
+    /// if the register doesn't exist.
+    pub fn set_readonly(&mut self, reg: usize, value: i32) -> Result<(), VMError> {
+        if reg >= MAX_REGISTERS {
+            return Err(VMError::InvalidRegister(reg));
+        }
+        self.readonly.retain(|&(r, _)| r != reg);
+        self.readonly.push((reg, value));
+        self.write_register(reg, value);
+        Ok(())
+    }
+
+    /// Heap memory of the VM.
+    pub fn heap(&self) -> &[u8] {
+        &self.heap
+    }
+
+    /// Mutable access to the heap memory of the VM.
+    pub fn heap_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.heap
+    }
+
+    /// Checks the specified registers which are expected to hold heap
+    /// addresses. Returns the ones that point outside the current heap,
+    /// including registers that don't exist.
+    pub fn validate_heap_pointers(&self, regs: &[usize]) -> Vec<usize> {
+        regs.iter()
+            .copied()
+            .filter(|&r| match self.registers.get(r) {
+                Some(&addr) => addr < 0 || addr as usize >= self.heap.len(),
+                None => true,
+            })
+            .collect()
+    }
+
+    // Executes the next instruction. Returns the reason if the program
+    // has terminated.
+    fn execute_instruction(&mut self) -> Result<Option<HaltReason>, VMError> {
+        let code_end = self.code_end();
+
+        // Register operands are checked up front so that an instruction that
+        // fails on a register has no effect.
+        if self.pc < code_end {
+            let registers = self.register_operands();
+            if let Some(reg) = registers
+                .iter()
+                .flatten()
+                .find(|&&reg| reg >= MAX_REGISTERS)
+            {
+                return Err(VMError::InvalidRegister(*reg));
+            }
+            if self.strict_registers {
+                if let Some(reg) = self.uninitialized_source(&registers) {
+                    return Err(VMError::UninitializedRegister(reg));
+                }
+            }
+        }
+
+        if self.pc < code_end {
+            self.cycles += 1;
+        }
+        if self.pc_history_len > 0 && self.pc < code_end {
+            if self.pc_history.len() == self.pc_history_len {
+                self.pc_history.pop_front();
+            }
+            self.pc_history
+                .push_back((self.pc, Opcode::from(self.program[self.pc])));
+        }
+
+        let result = self.execute_opcode();
+
+        // Discard whatever the instruction wrote to the read-only registers.
+        for &(reg, value) in &self.readonly {
+            self.registers[reg] = value;
+        }
+
+        if let Some(reg) = self.invalid_register.take() {
+            return Err(VMError::InvalidRegister(reg));
+        }
+
+        if let (Ok(Some(reason)), Some(hook)) = (&result, &mut self.halt_hook) {
+            (hook.0)(*reason);
+        }
+        result
+    }
+
+    // Returns the registers named by the operands of the instruction at the
+    // PC, indexed by operand. Other operands are None.
+    fn register_operands(&self) -> [Option<usize>; 3] {
+        let mut registers = [None; 3];
+        let opcode = Opcode::from(self.program[self.pc]);
+        let mut offset = self.pc + 1;
+        for (n, kind) in operand_kinds(opcode).iter().enumerate() {
+            match kind {
+                OperandKind::Register => {
+                    registers[n] = self.program.get(offset).map(|&reg| usize::from(reg));
+                    offset += 1;
+                }
+                OperandKind::Immediate => offset += 2,
+                OperandKind::WideImmediate => offset += 4,
+            }
+        }
+        registers
+    }
+
+    // Returns the first register read by the instruction at the PC that was
+    // never written. The registers must exist.
+    fn uninitialized_source(&self, registers: &[Option<usize>; 3]) -> Option<usize> {
+        let opcode = Opcode::from(self.program[self.pc]);
+        let mut sources: Vec<usize> = source_operands(opcode)
+            .iter()
+            .filter_map(|&n| registers[n])
+            .collect();
+
+        // RMOV and SMW also read the range of registers that they copy.
+        let range = match (opcode, registers) {
+            (Opcode::RMOV, [_, Some(base), Some(count)]) => Some((*base, *count)),
+            (Opcode::SMW, [Some(base), _, Some(count)]) => Some((*base, *count)),
+            _ => None,
+        };
+        if let Some((base, count)) = range {
+            let count = self.registers[count] as usize;
+            if count <= MAX_REGISTERS && base + count <= MAX_REGISTERS {
+                sources.extend(base..base + count);
+            }
+        }
+        sources
+            .into_iter()
+            .find(|&reg| self.written & (1 << reg) == 0)
+    }
+
+    // Offset just past the last instruction. The data section follows the
+    // code so execution stops where it starts.
+    fn code_end(&self) -> usize {
+        match BinaryHeader::parse(&self.program) {
+            Ok(header) if header.data_start != 0 => {
+                (header.data_start as usize).min(self.program.len())
+            }
+            _ => self.program.len(),
+        }
+    }
+
+    fn execute_opcode(&mut self) -> Result<Option<HaltReason>, VMError> {
+        if self.pc >= self.code_end() {
+            return Ok(Some(HaltReason::EndOfProgram));
+        }
+
+        let opcode = self.decode_opcode();
+
+        // Run the instruction on the configured executor. It's taken out of
+        // the VM for the duration of the call so it can borrow the VM.
+        match self.executor.take() {
+            Some(mut executor) => {
+                let result = self.dispatch(executor.as_mut(), opcode);
+                self.executor = Some(executor);
+                result
+            }
+            None => self.dispatch(&mut InterpreterExecutor, opcode),
+        }
+    }
+
+    // Hands the decoded instruction over to the executor method that
+    // handles its opcode group.
+    fn dispatch(
+        &mut self,
+        executor: &mut dyn Executor,
+        opcode: Opcode,
+    ) -> Result<Option<HaltReason>, VMError> {
+        match opcode {
+            Opcode::HLT
+            | Opcode::HALTR
+            | Opcode::JMP
+            | Opcode::JMPF
+            | Opcode::JMPB
+            | Opcode::JMPI
+            | Opcode::JMPFI
+            | Opcode::JMPBI
+            | Opcode::JEQ
+            | Opcode::JNEQ
+            | Opcode::JO
+            | Opcode::JNO
+            | Opcode::JEQI
+            | Opcode::JNEQI
+            | Opcode::JOI
+            | Opcode::JNOI
+            | Opcode::JNEG
+            | Opcode::JPOS
+            | Opcode::DBNZ
+            | Opcode::CALL
+            | Opcode::CALLI
+            | Opcode::NOP
+            | Opcode::RET => return executor.control(self, opcode),
+            Opcode::SHLI
+            | Opcode::SHRI
+            | Opcode::SHL
+            | Opcode::SHR
+            | Opcode::ABSDIFF
+            | Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::DIV
+            | Opcode::DIVMOD
+            | Opcode::MOD
+            | Opcode::INC
+            | Opcode::DEC
+            | Opcode::BSWAP
+            | Opcode::NEG
+            | Opcode::ABS => executor.arithmetic(self, opcode)?,
+            Opcode::EQ
+            | Opcode::NEQ
+            | Opcode::GT
+            | Opcode::GTE
+            | Opcode::LT
+            | Opcode::LTE
+            | Opcode::SELECT
+            | Opcode::SETZ
+            | Opcode::SETNZ
+            | Opcode::FLAGLOG => executor.comparison(self, opcode)?,
+            Opcode::LOAD
+            | Opcode::LOADW
+            | Opcode::MOV
+            | Opcode::RMOV
+            | Opcode::LMW
+            | Opcode::SMW
+            | Opcode::ALOC
+            | Opcode::BZERO
+            | Opcode::XORRANGE
+            | Opcode::INARG
+            | Opcode::LPROG
+            | Opcode::SPROG
+            | Opcode::AMOADD
+            | Opcode::XCHG
+            | Opcode::LOADM
+            | Opcode::STOREM => executor.memory(self, opcode)?,
+            Opcode::SP | Opcode::PUSH | Opcode::POP => executor.stack(self, opcode)?,
+            Opcode::SYS | Opcode::CLOCK => executor.system(self, opcode)?,
+            _ => return Err(VMError::IllegalOpcode(self.program[self.pc - 1])),
+        }
+        Ok(None)
+    }
+
+    fn read_register(&mut self) -> i32 {
+        let i = self.next_8_bits() as usize;
+        self.get_register(i)
+    }
+
+    // Reads a register on behalf of the executing instruction. Registers that
+    // don't exist are rejected before the instruction runs so they only read
+    // as 0 here.
+    fn get_register(&mut self, i: usize) -> i32 {
+        if i >= MAX_REGISTERS {
+            self.invalid_register.get_or_insert(i);
+            return 0;
+        }
+        self.registers[i]
+    }
+
+    // Writes a register on behalf of the executing instruction. Writes to
+    // registers that don't exist are recorded and dropped.
+    fn write_register(&mut self, i: usize, value: i32) {
+        if i >= MAX_REGISTERS {
+            self.invalid_register.get_or_insert(i);
+            return;
+        }
+        self.registers[i] = value;
+        self.written |= 1 << i;
+    }
+
+    fn next_8_bits(&mut self) -> u8 {
+        let result = self.program[self.pc];
+        self.pc += 1;
+        result
+    }
+
+    fn skip_bytes(&mut self, n: usize) {
+        self.pc += n;
+    }
+
+    fn next_16_bits(&mut self) -> u16 {
+        let result = u16::from(self.program[self.pc]) << 8 | u16::from(self.program[self.pc + 1]);
+        self.pc += 2;
+        result
+    }
+
+    fn next_32_bits(&mut self) -> u32 {
+        let result = u32::from(self.next_16_bits()) << 16;
+        result | u32::from(self.next_16_bits())
+    }
+
+    fn decode_opcode(&mut self) -> Opcode {
+        let opcode = Opcode::from(self.program[self.pc]);
+        self.pc += 1;
+        opcode
+    }
+}
+
+// Operands whose registers are read by the instruction, by position.
+// Registers that are only written aren't listed.
+fn source_operands(opcode: Opcode) -> &'static [usize] {
+    match opcode {
+        Opcode::JMP
+        | Opcode::JMPF
+        | Opcode::JMPB
+        | Opcode::JEQ
+        | Opcode::JNEQ
+        | Opcode::JO
+        | Opcode::JNO
+        | Opcode::CALL
+        | Opcode::ALOC
+        | Opcode::PUSH
+        | Opcode::SHLI
+        | Opcode::SHRI
+        | Opcode::INC
+        | Opcode::DEC
+        | Opcode::FLAGLOG => &[0],
+        Opcode::BSWAP
+        | Opcode::NEG
+        | Opcode::ABS
+        | Opcode::MOV
+        | Opcode::LOADM
+        | Opcode::INARG
+        | Opcode::LPROG => &[1],
+        Opcode::JNEG
+        | Opcode::JPOS
+        | Opcode::DBNZ
+        | Opcode::ADD
+        | Opcode::SUB
+        | Opcode::MUL
+        | Opcode::DIV
+        | Opcode::MOD
+        | Opcode::DIVMOD
+        | Opcode::ABSDIFF
+        | Opcode::EQ
+        | Opcode::NEQ
+        | Opcode::GT
+        | Opcode::GTE
+        | Opcode::LT
+        | Opcode::LTE
+        | Opcode::STOREM
+        | Opcode::BZERO
+        | Opcode::XCHG
+        | Opcode::SPROG => &[0, 1],
+        Opcode::SHL
+        | Opcode::SHR
+        | Opcode::SELECT
+        | Opcode::LMW
+        | Opcode::SMW
+        | Opcode::AMOADD
+        | Opcode::XORRANGE => &[1, 2],
+        Opcode::RMOV => &[2],
+        _ => &[],
+    }
+}
+
+// Parses the header of an executable and makes sure that the VM can run it.
+fn parse_header(bytes: &[u8]) -> Result<BinaryHeader, VMError> {
+    let header = match BinaryHeader::parse(bytes) {
+        Ok(header) => header,
+        Err(_) => return Err(VMError::InvalidHeader),
+    };
+    if header.version != BIN_VERSION {
+        return Err(VMError::UnsupportedVersion(header.version));
+    }
+    Ok(header)
+}
+
+// Built-in SYSCALL_PRINT_INT handler.
+fn sys_print_int(vm: &mut VM) {
+    let value = vm.register(0);
+    vm.write_output(&format!("{}\n", value));
+}
+
+// Built-in SYSCALL_READ_INT handler. Input that isn't a number reads as 0.
+fn sys_read_int(vm: &mut VM) {
+    let mut line = String::new();
+    let value = match io::stdin().read_line(&mut line) {
+        Ok(_) => line.trim().parse().unwrap_or(0),
+        Err(_) => 0,
+    };
+    vm.write_register(0, value);
+}
+
+// This is a helper structure use to iterate over the VM's registers. Its
+// mainly used in the REPL.
+pub struct Registers {
+    registers: [i32; MAX_REGISTERS],
+    i: usize,
+}
+
+impl Registers {
+    fn new(vm: &VM) -> Self {
+        Registers {
+            registers: vm.registers,
+            i: 0,
+        }
+    }
+}
+
+impl Iterator for Registers {
+    type Item = i32;
+
+    fn next(&mut self) -> Option<i32> {
+        if self.i < MAX_REGISTERS {
+            let result = self.registers[self.i];
+            self.i += 1;
+            return Some(result);
+        }
+        None
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
+}
+
+impl VM {
+    pub fn registers(&self) -> Registers {
+        Registers::new(self)
+    }
+}
+
+//------ End of Registers iterator region.
+
+/// Point-in-time copy of the VM's registers and flags. Its mainly used to
+/// show what an instruction changed while debugging.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    registers: [i32; MAX_REGISTERS],
+    equal_flag: bool,
+    remainder: u32,
+}
+
+/// A single difference between two snapshots.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateChange {
+    Register { index: usize, old: i32, new: i32 },
+    EqualFlag { old: bool, new: bool },
+    Remainder { old: u32, new: u32 },
+}
+
+impl fmt::Display for StateChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateChange::Register { index, old, new } => {
+                write!(f, "${}: {} -> {}", index, old, new)
+            }
+            StateChange::EqualFlag { old, new } => write!(f, "Equal Flag: {} -> {}", old, new),
+            StateChange::Remainder { old, new } => write!(f, "Remainder: {} -> {}", old, new),
+        }
+    }
+}
+
+impl Snapshot {
+    /// Returns everything that differs in the `after` snapshot.
+    pub fn diff(&self, after: &Snapshot) -> Vec<StateChange> {
+        let mut changes = vec![];
+        for (index, (&old, &new)) in self.registers.iter().zip(&after.registers).enumerate() {
+            if old != new {
+                changes.push(StateChange::Register { index, old, new });
+            }
+        }
+
+        if self.equal_flag != after.equal_flag {
+            changes.push(StateChange::EqualFlag {
+                old: self.equal_flag,
+                new: after.equal_flag,
+            });
+        }
+
+        if self.remainder != after.remainder {
+            changes.push(StateChange::Remainder {
+                old: self.remainder,
+                new: after.remainder,
+            });
+        }
+        changes
+    }
+}
+
+impl VM {
+    /// Captures the current registers and flags.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            equal_flag: self.equal_flag,
+            remainder: self.remainder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{Assembler, BIN_HEADER_LENGTH};
+    use crate::disassembler::disassemble_with_symbols;
+
+    fn get_vm() -> VM {
+        let mut vm = VM::new();
+        vm.program
+            .extend_from_slice(&BinaryHeader::new().to_bytes());
+        vm
+    }
+
+    #[test]
+    fn test_create_vm() {
+        let test_vm = VM::new();
+        assert_eq!(test_vm.registers, [0; MAX_REGISTERS]);
+    }
+
+    #[test]
+    fn test_hlt() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::HLT as u8, 0];
+        assert_eq!(Ok(Some(HaltReason::Halted)), vm.execute_instruction());
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_haltr() {
+        let mut vm = get_vm();
+        vm.add_bytes(&[Opcode::LOAD as u8, 1, 0, 42, Opcode::HALTR as u8, 0, 0, 0]);
+        assert_eq!(None, vm.result_registers());
+        assert_eq!(Ok(HaltReason::HaltedWithResult), vm.run());
+
+        let result = vm.result_registers().unwrap();
+        assert_eq!(MAX_REGISTERS, result.len());
+        assert_eq!(42, result[1]);
+
+        let mut vm = get_vm();
+        vm.add_bytes(&[Opcode::HLT as u8, 0, 0, 0]);
+        vm.run().unwrap();
+        assert_eq!(None, vm.result_registers());
+    }
+
+    #[test]
+    fn test_load() {
+        let mut vm = VM::new();
+        // LOAD #0 500
+        vm.program = vec![Opcode::LOAD as u8, 0, 1, 244];
+        assert_eq!(Ok(None), vm.execute_instruction());
+        assert_eq!(vm.registers[0], 500);
+    }
+
+    #[test]
+    fn test_loadw() {
+        let mut vm = VM::new();
+        // LOADW $2 #1000000
+        vm.program = vec![Opcode::LOADW as u8, 2, 0x00, 0x0F, 0x42, 0x40, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.registers[2], 1_000_000);
+        assert_eq!(vm.pc, 8);
+    }
+
+    #[test]
+    fn test_load_wide_values() {
+        let program = Assembler::new()
+            .assemble("load $0 #1000000\nload $1 #-1\nload $2 #7\nhlt")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        vm.run().unwrap();
+        assert_eq!(vm.register(0), 1_000_000);
+        assert_eq!(vm.register(1), -1);
+        assert_eq!(vm.register(2), 7);
+    }
+
+    #[test]
+    fn test_shli() {
+        let mut vm = VM::new();
+        vm.registers[0] = 3;
+        // SHLI $0 #4 followed by SHLI $0 #40
+        let shli = Opcode::SHLI as u8;
+        vm.program = vec![shli, 0, 0, 4, shli, 0, 0, 40];
+        vm.execute_instruction().unwrap();
+        assert_eq!(48, vm.register(0));
+        assert_eq!(4, vm.pc);
+
+        // Shift amount is masked to 40 % 32 = 8.
+        vm.execute_instruction().unwrap();
+        assert_eq!(48 << 8, vm.register(0));
+    }
+
+    #[test]
+    fn test_shri() {
+        let mut vm = VM::new();
+        vm.registers[0] = -256;
+        // SHRI $0 #4 followed by SHRI $0 #36
+        let shri = Opcode::SHRI as u8;
+        vm.program = vec![shri, 0, 0, 4, shri, 0, 0, 36];
+        vm.execute_instruction().unwrap();
+        assert_eq!(-16, vm.register(0));
+        vm.execute_instruction().unwrap();
+        assert_eq!(-1, vm.register(0));
+    }
+
+    #[test]
+    fn test_shl_shr() {
+        let mut vm = VM::new();
+        vm.registers[1] = -16;
+        vm.registers[2] = 2;
+        // SHL $0 $1 $2 followed by SHR $3 $1 $2
+        vm.program = vec![Opcode::SHL as u8, 0, 1, 2, Opcode::SHR as u8, 3, 1, 2];
+        vm.execute_instruction().unwrap();
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.register(0), -64);
+        assert_eq!(vm.register(3), -4);
+
+        // Amounts of 32 or more wrap around instead of panicking.
+        vm.registers[2] = 33;
+        vm.pc = 0;
+        vm.execute_instruction().unwrap();
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.register(0), -32);
+        assert_eq!(vm.register(3), -8);
+    }
+
+    #[test]
+    fn test_add() {
+        let mut vm = get_vm();
+        // LOAD $0 10 -> [1, 0, 0, 10]
+        // LOAD $1 10 -> [1, 1, 0, 10]
+        // ADD $0 $1 $2 -> [2, 0, 1, 2]
+        let load = Opcode::LOAD as u8;
+        let add = Opcode::ADD as u8;
+        vm.add_bytes(&[load, 0, 0, 10, load, 1, 0, 10, add, 0, 1, 2]);
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0], 10);
+        assert_eq!(vm.registers[1], 10);
+        assert_eq!(vm.registers[2], 20);
+    }
+
+    #[test]
+    fn test_mul() {
+        let mut vm = get_vm();
+        // LOAD $0 10 -> [1, 0, 0, 10]
+        // LOAD $1 10 -> [1, 1, 0, 10]
+        // MUL $0 $1 $2 -> [3, 0, 1, 2]
+        let load = Opcode::LOAD as u8;
+        let mul = Opcode::MUL as u8;
+        vm.add_bytes(&[load, 0, 0, 10, load, 1, 0, 10, mul, 0, 1, 2]);
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0], 10);
+        assert_eq!(vm.registers[1], 10);
+        assert_eq!(vm.registers[2], 100);
+    }
+
+    #[test]
+    fn test_sub() {
+        let mut vm = get_vm();
+        // LOAD $0 100 -> [1, 0, 0, 100]
+        // LOAD $1 10 -> [1, 1, 0, 10]
+        // SUB $0 $1 $2 -> [4, 0, 1, 2]
+        let load = Opcode::LOAD as u8;
+        let sub = Opcode::SUB as u8;
+        vm.add_bytes(&[load, 0, 0, 100, load, 1, 0, 10, sub, 0, 1, 2]);
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0], 100);
+        assert_eq!(vm.registers[1], 10);
+        assert_eq!(vm.registers[2], 90);
+    }
+
+    #[test]
+    fn test_div() {
+        let mut vm = get_vm();
+        // LOAD $0 21 -> [1, 0, 0, 21]
+        // LOAD $1 10 -> [1, 1, 0, 10]
+        // DIV $0 $1 $2 -> [5, 0, 1, 2]
+        let load = Opcode::LOAD as u8;
+        let div = Opcode::DIV as u8;
+        vm.add_bytes(&[load, 0, 0, 21, load, 1, 0, 10, div, 0, 1, 2]);
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0], 21);
+        assert_eq!(vm.registers[1], 10);
+        assert_eq!(vm.registers[2], 2);
+        assert_eq!(vm.remainder, 1);
+    }
+
+    #[test]
+    fn test_div_by_zero() {
+        let program = Assembler::new()
+            .assemble("load $0 #21\nload $1 #0\ndiv $0 $1 $2\nload $3 #1\nhlt")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+
+        // The VM stops right after the DIV without touching the destination.
+        assert_eq!(vm.run(), Err(VMError::DivisionByZero));
+        assert_eq!(vm.pc(), BIN_HEADER_LENGTH + 12);
+        assert_eq!(vm.register(2), 0);
+        assert_eq!(vm.register(3), 0);
+    }
+
+    #[test]
+    fn test_add_bytes_after_run() {
+        let mut vm = get_vm();
+        let load = Opcode::LOAD as u8;
+        let hlt = Opcode::HLT as u8;
+        vm.add_bytes(&[load, 0, 0, 1]);
+        assert_eq!(vm.run(), Ok(HaltReason::EndOfProgram));
+        assert_eq!(1, vm.register(0));
+
+        // Instructions appended after a run execute when the VM resumes.
+        vm.add_bytes(&[load, 0, 0, 2, load, 1, 0, 3, hlt, 0, 0, 0]);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(2, vm.register(0));
+        assert_eq!(3, vm.register(1));
+    }
+
+    #[test]
+    fn test_uninitialized_register() {
+        // ADD $0 $5 $1
+        let program = vec![Opcode::ADD as u8, 0, 5, 1];
+
+        let mut vm = VM::new();
+        vm.program = program.clone();
+        assert_eq!(vm.execute_instruction(), Ok(None));
+
+        let mut vm = VM::new().with_strict_registers(true);
+        vm.set_register(0, 1).unwrap();
+        vm.set_register(1, 9).unwrap();
+        vm.program = program.clone();
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::UninitializedRegister(5))
+        );
+        assert_eq!((vm.pc, vm.register(1)), (0, 9));
+
+        // Nothing the instruction did before the read is kept.
+        let mut vm = VM::new().with_strict_registers(true);
+        vm.program = vec![Opcode::PUSH as u8, 5, 0, 0];
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::UninitializedRegister(5))
+        );
+        assert_eq!((vm.pc, vm.stack.len(), vm.cycles()), (0, 0, 0));
+
+        // RMOV reads every register of the range it copies.
+        let mut vm = VM::new().with_strict_registers(true);
+        vm.set_register(1, 2).unwrap();
+        vm.set_register(4, 3).unwrap();
+        vm.program = vec![Opcode::RMOV as u8, 0, 4, 1];
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::UninitializedRegister(5))
+        );
+        assert_eq!((vm.pc, vm.register(0)), (0, 0));
+        vm.set_register(5, 6).unwrap();
+        assert_eq!(vm.execute_instruction(), Ok(None));
+        assert_eq!((vm.register(0), vm.register(1)), (3, 6));
+
+        let mut vm = VM::new().with_strict_registers(true);
+        vm.set_register(0, 1).unwrap();
+        vm.set_register(5, 2).unwrap();
+        vm.program = program;
+        assert_eq!(vm.execute_instruction(), Ok(None));
+        assert_eq!(vm.register(1), 3);
+    }
+
+    #[test]
+    fn test_set_register() {
+        let mut vm = VM::new();
+        assert_eq!(vm.set_register(3, 42), Ok(()));
+        assert_eq!(vm.register(3), 42);
+        assert_eq!(vm.registers().nth(3), Some(42));
+
+        assert_eq!(
+            vm.set_register(MAX_REGISTERS, 1),
+            Err(VMError::InvalidRegister(MAX_REGISTERS))
+        );
+        assert!(vm.registers().all(|value| value == 0 || value == 42));
+    }
+
+    #[test]
+    fn test_invalid_register() {
+        let load = Opcode::LOAD as u8;
+        let add = Opcode::ADD as u8;
+        let mut vm = VM::new();
+        vm.program = vec![load, 40, 0, 1];
+        assert_eq!(vm.execute_instruction(), Err(VMError::InvalidRegister(40)));
+        assert_eq!(vm.pc, 0);
+
+        vm.set_register(1, 9).unwrap();
+        vm.program = vec![add, 0, 255, 1];
+        assert_eq!(vm.execute_instruction(), Err(VMError::InvalidRegister(255)));
+        assert_eq!((vm.pc, vm.register(1)), (0, 9));
+
+        // The instruction doesn't run at all, so POP keeps the value.
+        vm.stack.push(5);
+        vm.program = vec![Opcode::POP as u8, 40, 0, 0];
+        assert_eq!(vm.execute_instruction(), Err(VMError::InvalidRegister(40)));
+        assert_eq!((vm.pc, vm.stack.clone()), (0, vec![5]));
+    }
+
+    #[test]
+    fn test_readonly_register() {
+        let mut vm = get_vm();
+        vm.set_readonly(0, 0).unwrap();
+        // LOAD $0 #10, LOAD $1 #10, ADD $1 $1 $0, INC $0
+        let load = Opcode::LOAD as u8;
+        let add = Opcode::ADD as u8;
+        let inc = Opcode::INC as u8;
+        vm.add_bytes(&[load, 0, 0, 10, load, 1, 0, 10, add, 1, 1, 0, inc, 0]);
+        vm.run().unwrap();
+        assert_eq!(vm.register(0), 0);
+        assert_eq!(vm.register(1), 10);
+
+        vm.set_readonly(0, 7).unwrap();
+        assert_eq!(vm.register(0), 7);
+
+        assert_eq!(
+            vm.set_readonly(MAX_REGISTERS, 1),
+            Err(VMError::InvalidRegister(MAX_REGISTERS))
+        );
+        assert_eq!(vm.readonly, vec![(0, 7)]);
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut vm = VM::new().with_clock(|_| Duration::from_millis(1234));
+        vm.program = vec![Opcode::CLOCK as u8, 3, 0, 0, Opcode::CLOCK as u8, 4, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.register(3), 1234);
+        assert_eq!(vm.pc, 4);
+
+        // Elapsed time saturates instead of wrapping.
+        vm.clock = |_| Duration::from_secs(u64::MAX);
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.register(4), i32::MAX);
+    }
+
+    #[test]
+    fn test_pc_history() {
+        let mut vm = get_vm().with_pc_history(3);
+        let load = Opcode::LOAD as u8;
+        let ret = Opcode::RET as u8;
+        vm.add_bytes(&[load, 0, 0, 1, load, 1, 0, 2, load, 2, 0, 3, ret, 0, 0, 0]);
+        assert_eq!(vm.run(), Err(VMError::StackUnderflow));
+
+        let h = BIN_HEADER_LENGTH;
+        assert_eq!(
+            vm.pc_history(),
+            vec![
+                (h + 4, Opcode::LOAD),
+                (h + 8, Opcode::LOAD),
+                (h + 12, Opcode::RET)
+            ]
+        );
+
+        // History is off by default.
+        let mut vm = get_vm();
+        vm.add_bytes(&[ret, 0, 0, 0]);
+        assert!(vm.run().is_err());
+        assert!(vm.pc_history().is_empty());
+    }
+
+    #[test]
+    fn test_clear_flags() {
+        let mut vm = VM::new();
+        vm.registers[2] = 7;
+        vm.equal_flag = true;
+        vm.overflow_flag = true;
+        vm.remainder = 3;
+        vm.clear_flags();
+        assert!(!vm.equal_flag);
+        assert!(!vm.overflow_flag);
+        assert_eq!(0, vm.remainder);
+        assert_eq!(7, vm.register(2));
+    }
+
+    #[test]
+    fn test_jmp() {
+        let mut vm = VM::new();
+        vm.registers[0] = 1;
+        vm.program = vec![Opcode::JMP as u8, 0, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_jmpf() {
+        let mut vm = VM::new();
+        vm.registers[0] = 2;
+        // JMPF $0
+        // 0, 0
+        // JMP $0
+        let jmpf = Opcode::JMPF as u8;
+        let jmp = Opcode::JMP as u8;
+        vm.program = vec![jmpf, 0, 0, 0, jmp, 0, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_jmpb() {
+        let mut vm = VM::new();
+        vm.registers[0] = 4;
+        vm.registers[1] = 2;
+        // JMP $0
+        // 0, 0
+        // JMPB $0
+        //
+        //  This is practically a loop {} given that JMPB is 2 bytes and we are asking it to go
+        //  back 2-bytes.
+        let jmp = Opcode::JMP as u8;
+        let jmpb = Opcode::JMPB as u8;
+        vm.program = vec![jmp, 0, 0, 0, jmpb, 1, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_illegal_opcode() {
+        let mut vm = VM::new();
+        vm.program = vec![255];
+        assert_eq!(Err(VMError::IllegalOpcode(255)), vm.execute_instruction());
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_eq() {
+        let mut vm = VM::new();
+        vm.registers[0] = 99;
+        vm.registers[1] = 99;
+        // EQ $0 $1
+        // EQ $0 $1
+        let eq = Opcode::EQ as u8;
+        vm.program = vec![eq, 0, 1, 0, eq, 0, 1, 0];
+        assert!(!vm.equal_flag);
+        vm.execute_instruction().unwrap();
+        assert!(vm.equal_flag);
+
+        vm.registers[1] = 10;
+        vm.execute_instruction().unwrap();
+        assert!(!vm.equal_flag);
+    }
+
+    #[test]
+    fn test_neq() {
+        let mut vm = VM::new();
+        vm.registers[0] = 99;
+        vm.registers[1] = 99;
+        // NEQ $0 $1
+        // NEQ $0 $1
+        let neq = Opcode::NEQ as u8;
+        vm.program = vec![neq, 0, 1, 0, neq, 0, 1, 0];
+        vm.execute_instruction().unwrap();
+        assert!(!vm.equal_flag);
+
+        vm.registers[1] = 10;
+        vm.execute_instruction().unwrap();
+        assert!(vm.equal_flag);
+    }
+
+    #[test]
+    fn test_gt() {
+        let mut vm = VM::new();
+        vm.registers[0] = 100;
+        vm.registers[1] = 99;
+        // GT $0 $1
+        // GT $0 $1
+        let gt = Opcode::GT as u8;
+        vm.program = vec![gt, 0, 1, 0, gt, 0, 1, 0];
+        vm.execute_instruction().unwrap();
+        assert!(vm.equal_flag);
+
+        vm.registers[0] = 10;
+        vm.execute_instruction().unwrap();
+        assert!(!vm.equal_flag);
+    }
+
+    #[test]
+    fn test_gte() {
+        let mut vm = VM::new();
+        vm.registers[0] = 100;
+        vm.registers[1] = 99;
+        // GTE $0 $1
+        // GTE $0 $1
+        // GTE $0 $1
+        let gte = Opcode::GTE as u8;
+        vm.program = vec![gte, 0, 1, 0, gte, 0, 1, 0, gte, 0, 1, 0];
+        vm.execute_instruction().unwrap();
+        assert!(vm.equal_flag);
+
+        vm.registers[0] = 99;
+        vm.execute_instruction().unwrap();
+        assert!(vm.equal_flag);
+
+        vm.registers[0] = 9;
+        vm.execute_instruction().unwrap();
+        assert!(!vm.equal_flag);
+    }
+
+    #[test]
+    fn test_lt() {
+        let mut vm = VM::new();
+        vm.registers[0] = 10;
+        vm.registers[1] = 99;
+        // LT $0 $1
+        // LT $0 $1
+        let lt = Opcode::LT as u8;
+        vm.program = vec![lt, 0, 1, 0, lt, 0, 1, 0];
+        vm.execute_instruction().unwrap();
+        assert!(vm.equal_flag);
+
+        vm.registers[0] = 100;
+        vm.execute_instruction().unwrap();
+        assert!(!vm.equal_flag);
+    }
+
+    #[test]
+    fn test_lte() {
+        let mut vm = VM::new();
+        vm.registers[0] = 100;
+        vm.registers[1] = 99;
+        // LTE $0 $1
+        // LTE $0 $1
+        // LTE $0 $1
+        let lte = Opcode::LTE as u8;
+        vm.program = vec![lte, 0, 1, 0, lte, 0, 1, 0, lte, 0, 1, 0];
+        vm.execute_instruction().unwrap();
+        assert!(!vm.equal_flag);
+
+        vm.registers[0] = 99;
+        vm.execute_instruction().unwrap();
+        assert!(vm.equal_flag);
+
+        vm.registers[1] = 199;
+        vm.execute_instruction().unwrap();
+        assert!(vm.equal_flag);
+    }
+
+    #[test]
+    fn test_mod() {
+        let mut vm = VM::new();
+        // MOD $0 $1 $2
+        vm.program = vec![Opcode::MOD as u8, 0, 1, 2];
+        for &(a, b, expected) in &[(23, 5, 3), (-23, 5, -3), (23, -5, 3), (-23, -5, -3)] {
+            vm.registers[0] = a;
+            vm.registers[1] = b;
+            vm.pc = 0;
+            vm.execute_instruction().unwrap();
+            assert_eq!(vm.register(2), expected);
+        }
+
+        vm.registers[0] = i32::MIN;
+        vm.registers[1] = -1;
+        vm.pc = 0;
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.register(2), 0);
+
+        vm.registers[1] = 0;
+        vm.pc = 0;
+        assert_eq!(vm.execute_instruction(), Err(VMError::DivisionByZero));
+    }
+
+    #[test]
+    fn test_divmod() {
+        let mut vm = VM::new();
+        vm.registers[0] = 23;
+        vm.registers[1] = 5;
+        // DIVMOD $0 $1 $2
+        vm.program = vec![Opcode::DIVMOD as u8, 0, 1, 2];
+        vm.execute_instruction().unwrap();
+        assert_eq!(4, vm.register(2));
+        assert_eq!(3, vm.register(3));
+        assert_eq!(4, vm.pc);
+
+        vm.registers[1] = 0;
+        vm.pc = 0;
+        assert_eq!(vm.execute_instruction(), Err(VMError::DivisionByZero));
+
+        // The remainder would go past the last register.
+        vm.registers[1] = 5;
+        vm.program = vec![Opcode::DIVMOD as u8, 0, 1, 31];
+        vm.pc = 0;
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidRegisterRange { base: 31, count: 2 })
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_overflow() {
+        // Overflowing results wrap around and set the overflow flag.
+        for &(opcode, a, b, expected) in &[
+            (Opcode::ADD, i32::MAX, 1, i32::MIN),
+            (Opcode::SUB, i32::MIN, 1, i32::MAX),
+            (Opcode::MUL, i32::MAX, 2, -2),
+            (Opcode::DIV, i32::MIN, -1, i32::MIN),
+            (Opcode::MOD, i32::MIN, -1, 0),
+            (Opcode::ABSDIFF, i32::MAX, -1, i32::MIN),
+        ] {
+            let mut vm = VM::new();
+            vm.registers[0] = a;
+            vm.registers[1] = b;
+            vm.program = vec![opcode as u8, 0, 1, 2, opcode as u8, 1, 1, 3];
+            vm.execute_instruction().unwrap();
+            assert!(vm.overflow_flag, "{:?} didn't overflow", opcode);
+            assert_eq!(vm.registers[2], expected);
+
+            // The next operation that doesn't overflow clears the flag.
+            vm.execute_instruction().unwrap();
+            assert!(!vm.overflow_flag);
+        }
+    }
+
+    #[test]
+    fn test_shift_and_step_overflow() {
+        // Shifting by the width of a register or more masks the amount.
+        let mut vm = VM::new();
+        vm.registers[0] = 1;
+        vm.registers[1] = 40;
+        vm.program = vec![Opcode::SHL as u8, 2, 0, 1, Opcode::SHRI as u8, 2, 0, 4];
+        vm.execute_instruction().unwrap();
+        assert!(vm.overflow_flag);
+        assert_eq!(vm.registers[2], 256);
+        vm.execute_instruction().unwrap();
+        assert!(!vm.overflow_flag);
+        assert_eq!(vm.registers[2], 16);
+
+        // INC and DEC wrap around instead of panicking.
+        vm.registers[0] = i32::MAX;
+        vm.registers[1] = i32::MIN;
+        vm.program = vec![Opcode::INC as u8, 0, 0, 0, Opcode::DEC as u8, 1, 0, 0];
+        vm.pc = 0;
+        vm.execute_instruction().unwrap();
+        assert!(vm.overflow_flag);
+        assert_eq!(vm.registers[0], i32::MIN);
+        vm.overflow_flag = false;
+        vm.execute_instruction().unwrap();
+        assert!(vm.overflow_flag);
+        assert_eq!(vm.registers[1], i32::MAX);
+        assert_eq!(vm.pc, 8);
+    }
+
+    #[test]
+    fn test_jo_after_inc() {
+        let program = Assembler::new()
+            .assemble("load $0 #2147483647\ninc $0\njo @overflow\nhlt\noverflow: load $1 #1\nhlt")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(0), i32::MIN);
+        assert_eq!(vm.register(1), 1);
+    }
+
+    #[test]
+    fn test_jo() {
+        let add = Opcode::ADD as u8;
+        let jo = Opcode::JO as u8;
+
+        // ADD $0 $1 $2 overflows so JO $3 jumps.
+        let mut vm = VM::new();
+        vm.registers[0] = i32::MAX;
+        vm.registers[1] = 1;
+        vm.registers[3] = 100;
+        vm.program = vec![add, 0, 1, 2, jo, 3, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert!(vm.overflow_flag);
+        assert_eq!(vm.registers[2], i32::MIN);
+        vm.execute_instruction().unwrap();
+        assert_eq!(100, vm.pc);
+
+        // Without overflow, JO falls through and the flag is cleared.
+        vm.registers[0] = 1;
+        vm.pc = 0;
+        vm.execute_instruction().unwrap();
+        assert!(!vm.overflow_flag);
+        vm.execute_instruction().unwrap();
+        assert_eq!(8, vm.pc);
+    }
+
+    #[test]
+    fn test_jno() {
+        let mut vm = VM::new();
+        vm.registers[0] = 5;
+        vm.program = vec![Opcode::JNO as u8, 0, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(5, vm.pc);
+
+        vm.overflow_flag = true;
+        vm.pc = 0;
+        vm.execute_instruction().unwrap();
+        assert_eq!(4, vm.pc);
+    }
+
+    #[test]
+    fn test_flaglog() {
+        let load = Opcode::LOAD as u8;
+        let eq = Opcode::EQ as u8;
+        let flaglog = Opcode::FLAGLOG as u8;
+        let hlt = Opcode::HLT as u8;
+        let mut vm = get_vm();
+        vm.add_bytes(&[
+            load, 0, 0, 1, // LOAD $0 #1
+            load, 1, 0, 1, // LOAD $1 #1
+            load, 2, 0, 2, // LOAD $2 #2
+            eq, 0, 1, 0, // EQ $0 $1
+            flaglog, 5, 0, 0, // FLAGLOG $5
+            eq, 0, 2, 0, // EQ $0 $2
+            flaglog, 5, 0, 0, // FLAGLOG $5
+            eq, 1, 0, 0, // EQ $1 $0
+            flaglog, 5, 0, 0, // FLAGLOG $5
+            hlt, 0, 0, 0,
+        ]);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(5), 0b101);
+    }
+
+    #[test]
+    fn test_absdiff() {
+        let absdiff = Opcode::ABSDIFF as u8;
+        for &(a, b, expected) in &[(3, 10, 7), (10, 3, 7), (-5, 5, 10), (i32::MIN, 0, i32::MIN)] {
+            let mut vm = VM::new();
+            vm.registers[0] = a;
+            vm.registers[1] = b;
+            vm.program = vec![absdiff, 0, 1, 2];
+            vm.execute_instruction().unwrap();
+            assert_eq!(vm.registers[2], expected);
+        }
+    }
+
+    #[test]
+    fn test_nop() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::NOP as u8, 0xFF, 0xFF, 0xFF];
+        assert_eq!(vm.execute_instruction(), Ok(None));
+        assert_eq!(vm.pc, 4);
+        assert!(vm.registers().all(|value| value == 0));
+    }
+
+    #[test]
+    fn test_jmpi() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::JMPI as u8, 0, 12, 0xFF];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.pc, 12);
+    }
+
+    #[test]
+    fn test_conditional_immediate_jumps() {
+        let mut vm = VM::new();
+        for &(opcode, equal, overflow, taken) in &[
+            (Opcode::JEQI, true, false, true),
+            (Opcode::JEQI, false, false, false),
+            (Opcode::JNEQI, false, false, true),
+            (Opcode::JNEQI, true, false, false),
+            (Opcode::JOI, false, true, true),
+            (Opcode::JOI, false, false, false),
+            (Opcode::JNOI, false, false, true),
+            (Opcode::JNOI, false, true, false),
+        ] {
+            vm.program = vec![opcode as u8, 0, 12, 0xFF];
+            vm.pc = 0;
+            vm.equal_flag = equal;
+            vm.overflow_flag = overflow;
+            vm.execute_instruction().unwrap();
+            assert_eq!(vm.pc, if taken { 12 } else { 4 }, "{:?}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_relative_jumps() {
+        let mut vm = VM::new();
+        vm.program = vec![0; 16];
+        vm.program[4..8].copy_from_slice(&[Opcode::JMPFI as u8, 0, 8, 0xFF]);
+        vm.program[12..16].copy_from_slice(&[Opcode::JMPBI as u8, 0, 12, 0xFF]);
+        vm.pc = 4;
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.pc, 12);
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.pc, 0);
+
+        vm.program[0..4].copy_from_slice(&[Opcode::JMPBI as u8, 0, 4, 0xFF]);
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidProgramAddress(0))
+        );
+    }
+
+    #[test]
+    fn test_jneg_jpos() {
+        let jneg = Opcode::JNEG as u8;
+        let jpos = Opcode::JPOS as u8;
+        for &(value, jneg_pc, jpos_pc) in &[(-3, 100, 4), (7, 4, 100), (0, 4, 4)] {
+            let mut vm = VM::new();
+            vm.registers[0] = value;
+            vm.registers[1] = 100;
+
+            vm.program = vec![jneg, 0, 1, 0xFF];
+            vm.execute_instruction().unwrap();
+            assert_eq!(vm.pc, jneg_pc);
+
+            vm.pc = 0;
+            vm.program = vec![jpos, 0, 1, 0xFF];
+            vm.execute_instruction().unwrap();
+            assert_eq!(vm.pc, jpos_pc);
+        }
+    }
+
+    #[test]
+    fn test_dbnz() {
+        let mut vm = get_vm();
+        let load = Opcode::LOAD as u8;
+        let add = Opcode::ADD as u8;
+        let dbnz = Opcode::DBNZ as u8;
+        let hlt = Opcode::HLT as u8;
+        vm.add_bytes(&[
+            load, 0, 0, 5, // LOAD $0 #5
+            load, 2, 0, 3, // LOAD $2 #3
+            load, 3, 0, 76, // LOAD $3 #76
+            add, 1, 2, 1, // 76: ADD $1 $2 $1
+            dbnz, 0, 3, 0, // DBNZ $0 $3
+            hlt, 0, 0, 0,
+        ]);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(0), 0);
+        assert_eq!(vm.register(1), 15);
+    }
+
+    #[test]
+    fn test_jeq_jneq() {
+        let mut vm = VM::new();
+        vm.registers[0] = 12;
+        for &(opcode, equal, taken) in &[
+            (Opcode::JEQ, true, true),
+            (Opcode::JEQ, false, false),
+            (Opcode::JNEQ, false, true),
+            (Opcode::JNEQ, true, false),
+        ] {
+            vm.program = vec![opcode as u8, 0, 0xFF, 0xFF];
+            vm.pc = 0;
+            vm.equal_flag = equal;
+            vm.execute_instruction().unwrap();
+            assert_eq!(vm.pc, if taken { 12 } else { 4 }, "{:?}", opcode);
+        }
+
+        // Falling through lands on the next instruction.
+        let source = "load $0 #1\nload $1 #2\neq $0 $1\nload $2 #100\njeq $2\nload $3 #7\nhlt";
+        let program = Assembler::new().assemble(source).unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(3), 7);
+    }
+
+    #[test]
+    fn test_aloc() {
+        let mut vm = VM::new();
+        assert_eq!(0, vm.heap.len());
+        vm.registers[9] = 1024;
+        vm.program = vec![Opcode::ALOC as u8, 9, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(1024, vm.heap.len());
+        assert_eq!(4, vm.pc);
+
+        vm.registers[9] = -1;
+        vm.pc = 0;
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::NegativeAllocation(-1))
+        );
+        assert_eq!(1024, vm.heap.len());
+    }
+
+    #[test]
+    fn test_halt_hook() {
+        let reasons = Arc::new(Mutex::new(vec![]));
+        let mut vm = get_vm();
+        let recorded = Arc::clone(&reasons);
+        vm.set_halt_hook(Box::new(move |reason| {
+            recorded.lock().unwrap().push(reason)
+        }));
+
+        let load = Opcode::LOAD as u8;
+        let hlt = Opcode::HLT as u8;
+        vm.add_bytes(&[load, 0, 0, 1, load, 1, 0, 2, hlt, 0, 0, 0]);
+        vm.pc = BIN_HEADER_LENGTH;
+        assert_eq!(vm.execute_instruction(), Ok(None));
+        assert_eq!(vm.execute_instruction(), Ok(None));
+        assert!(reasons.lock().unwrap().is_empty());
+
+        assert_eq!(vm.execute_instruction(), Ok(Some(HaltReason::Halted)));
+        assert_eq!(*reasons.lock().unwrap(), vec![HaltReason::Halted]);
+
+        // Errors don't count as halting.
+        let mut vm = get_vm();
+        let recorded = Arc::clone(&reasons);
+        vm.set_halt_hook(Box::new(move |reason| {
+            recorded.lock().unwrap().push(reason)
+        }));
+        vm.add_bytes(&[Opcode::IGL as u8, 0, 0, 0]);
+        assert_eq!(vm.run(), Err(VMError::IllegalOpcode(255)));
+        assert_eq!(reasons.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_builder() {
+        let add = Opcode::ADD as u8;
+        let bzero = Opcode::BZERO as u8;
+        let hlt = Opcode::HLT as u8;
+        let mut program = BinaryHeader::new().to_bytes().to_vec();
+        program.extend_from_slice(&[
+            add, 0, 1, 2, // ADD $0 $1 $2
+            bzero, 3, 4, 0, // BZERO $3 $4
+            hlt, 0, 0, 0,
+        ]);
+
+        let mut vm = VM::builder()
+            .register(0, 40)
+            .register(1, 2)
+            .register(3, 1)
+            .register(4, 2)
+            .heap(vec![1, 2, 3, 4])
+            .program(program)
+            .build()
+            .unwrap();
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(2), 42);
+        assert_eq!(vm.heap(), &[1, 0, 0, 4]);
+
+        // The heap can't grow beyond its limit.
+        let mut vm = VM::builder()
+            .register(0, 5)
+            .heap(vec![0; 4])
+            .program(vec![Opcode::ALOC as u8, 0, 0, 0])
+            .max_heap(8)
+            .build()
+            .unwrap();
+        assert_eq!(vm.execute_instruction(), Err(VMError::HeapLimitExceeded(9)));
+        assert_eq!(vm.heap().len(), 4);
+
+        assert_eq!(
+            VM::builder().register(MAX_REGISTERS, 1).build().err(),
+            Some(VMError::InvalidRegister(MAX_REGISTERS))
+        );
+        assert_eq!(
+            VM::builder().heap(vec![0; 4]).max_heap(2).build().err(),
+            Some(VMError::HeapLimitExceeded(4))
+        );
+    }
+
+    #[test]
+    fn test_builder_with_data() {
+        let program = Assembler::new()
+            .assemble(".data\nw: .word #5\n.code\nhlt")
+            .unwrap();
+
+        // The data section only overwrites the start of the heap.
+        let mut vm = VM::builder()
+            .heap(vec![9; 8])
+            .program(program.clone())
+            .build()
+            .unwrap();
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.heap(), &[0, 5, 9, 9, 9, 9, 9, 9]);
+
+        let mut vm = VM::builder().program(program).max_heap(1).build().unwrap();
+        assert_eq!(vm.run(), Err(VMError::HeapLimitExceeded(2)));
+    }
+
+    #[test]
+    fn test_aloc_heap_fill() {
+        let mut vm = VM::new();
+        vm.heap = vec![1, 2];
+        vm.set_heap_fill(0xAA);
+        vm.registers[0] = 4;
+        vm.program = vec![Opcode::ALOC as u8, 0, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vec![1, 2, 0xAA, 0xAA, 0xAA, 0xAA], vm.heap);
+    }
+
+    #[test]
+    fn test_load_from_file() {
+        let path = std::env::temp_dir().join("iridium_test_load_from_file.iridium");
+        Assembler::new()
+            .assemble_to_file("load $0 #7\nload $1 #1000000\nhlt", &path)
+            .unwrap();
+        let mut vm = VM::new();
+        let loaded = vm.load_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(0), 7);
+        assert_eq!(vm.register(1), 1_000_000);
+    }
+
+    #[test]
+    fn test_load_from_file_errors() {
+        let path = std::env::temp_dir().join("iridium_test_load_bad_magic.iridium");
+        std::fs::write(&path, [0; BIN_HEADER_LENGTH + 4]).unwrap();
+        let mut vm = VM::new();
+        let loaded = vm.load_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let e = loaded.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "invalid binary header");
+
+        // Nothing is written if the program doesn't assemble.
+        let path = std::env::temp_dir().join("iridium_test_assemble_error.iridium");
+        let e = Assembler::new()
+            .assemble_to_file("load $0 @missing\nhlt", &path)
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+        assert_eq!(
+            vm.load_from_file(&path).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_load_symbols() {
+        let mut assembler = Assembler::new();
+        let program = assembler
+            .assemble("load $0 #3\nloop: dec $0\njmp @loop\n.data\ncount: .word #1")
+            .unwrap();
+
+        let path = std::env::temp_dir().join("iridium_test_load_symbols.map");
+        assembler.write_symbol_map(&path).unwrap();
+        let mut vm = VM::new();
+        vm.load_symbols(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Only code labels end up in the map.
+        assert_eq!(vm.symbols().len(), 1);
+        assert_eq!(vm.symbols().get("loop"), Some(&(BIN_HEADER_LENGTH + 4)));
+        assert_eq!(vm.describe_address(BIN_HEADER_LENGTH + 4), "0044 <loop>");
+
+        let code = &program[BIN_HEADER_LENGTH..BIN_HEADER_LENGTH + 12];
+        assert_eq!(
+            disassemble_with_symbols(code, false, vm.symbols()),
+            "LOAD $0 #3\nloop:\nDEC $0\nJMPI #68\n"
+        );
+    }
+
+    #[test]
+    fn test_validate_heap_pointers() {
+        let mut vm = VM::new();
+        vm.heap = vec![0; 16];
+        vm.registers[1] = 15;
+        vm.registers[2] = 16;
+        vm.registers[3] = -1;
+        assert_eq!(
+            vm.validate_heap_pointers(&[1, 2, 3, MAX_REGISTERS]),
+            vec![2, 3, MAX_REGISTERS]
+        );
+    }
+
+    #[test]
+    fn test_hexdump_heap() {
+        let mut vm = VM::new();
+        vm.heap = b"Hello, Iridium!\n\x00\x01".to_vec();
+        assert_eq!(
+            vm.hexdump_heap(0, 100),
+            "00000000  48 65 6c 6c 6f 2c 20 49 72 69 64 69 75 6d 21 0a  |Hello, Iridium!.|\n\
+             00000010  00 01                                            |..|\n"
+        );
+        assert_eq!(
+            vm.hexdump_heap(7, 4),
+            "00000007  49 72 69 64                                      |Irid|\n"
+        );
+        assert_eq!(vm.hexdump_heap(100, 4), "");
+    }
+
+    #[test]
+    fn test_inc() {
+        let mut vm = VM::new();
+        vm.registers[9] = 10;
+        vm.program = vec![Opcode::INC as u8, 9, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(11, vm.register(9));
+    }
+
+    #[test]
+    fn test_dec() {
+        let mut vm = VM::new();
+        vm.registers[9] = 22;
+        vm.program = vec![Opcode::DEC as u8, 9, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(21, vm.register(9));
+    }
+
+    #[test]
+    fn test_bswap() {
+        let mut vm = VM::new();
+        vm.registers[1] = 0x0102_0304;
+        vm.program = vec![Opcode::BSWAP as u8, 0, 1, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(0x0403_0201, vm.register(0));
+        assert_eq!(4, vm.pc);
+    }
+
+    #[test]
+    fn test_neg_and_abs() {
+        let mut vm = VM::new();
+        // NEG $0 $1 followed by ABS $2 $1
+        let neg = Opcode::NEG as u8;
+        let abs = Opcode::ABS as u8;
+        vm.program = vec![neg, 0, 1, 0, abs, 2, 1, 0];
+        for &(value, negated, absolute) in &[
+            (5, -5, 5),
+            (-7, 7, 7),
+            (0, 0, 0),
+            (i32::MAX, -i32::MAX, i32::MAX),
+        ] {
+            vm.registers[1] = value;
+            vm.pc = 0;
+            vm.execute_instruction().unwrap();
+            assert!(!vm.overflow_flag);
+            vm.execute_instruction().unwrap();
+            assert!(!vm.overflow_flag);
+            assert_eq!((vm.register(0), vm.register(2)), (negated, absolute));
+            assert_eq!(8, vm.pc);
+        }
+
+        // -i32::MIN doesn't fit so both saturate and report the overflow.
+        vm.registers[1] = i32::MIN;
+        vm.pc = 0;
+        vm.execute_instruction().unwrap();
+        assert!(vm.overflow_flag);
+        assert_eq!(vm.register(0), i32::MAX);
+        vm.clear_flags();
+        vm.execute_instruction().unwrap();
+        assert!(vm.overflow_flag);
+        assert_eq!(vm.register(2), i32::MAX);
+    }
+
+    #[test]
+    fn test_mov() {
+        let program = Assembler::new()
+            .assemble("load $0 #42\nmov $1 $0\nhlt")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!((vm.register(0), vm.register(1)), (42, 42));
+        assert_eq!(
+            &program[BIN_HEADER_LENGTH + 4..BIN_HEADER_LENGTH + 8],
+            &[Opcode::MOV as u8, 1, 0, 0xFF]
+        );
+    }
+
+    #[test]
+    fn test_rmov() {
+        let mut vm = VM::new();
+        vm.registers[0] = 3;
+        vm.registers[1] = 10;
+        vm.registers[2] = 20;
+        vm.registers[3] = 30;
+        // RMOV $8 $1 $0
+        vm.program = vec![Opcode::RMOV as u8, 8, 1, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(&vm.registers[8..11], &[10, 20, 30]);
+        assert_eq!(4, vm.pc);
+    }
+
+    #[test]
+    fn test_rmov_overlapping() {
+        let mut vm = VM::new();
+        vm.registers[0] = 3;
+        vm.registers[1] = 10;
+        vm.registers[2] = 20;
+        vm.registers[3] = 30;
+        // RMOV $2 $1 $0 followed by RMOV $1 $2 $0
+        vm.program = vec![Opcode::RMOV as u8, 2, 1, 0, Opcode::RMOV as u8, 1, 2, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(&vm.registers[1..5], &[10, 10, 20, 30]);
+        vm.registers[0] = 3;
+        vm.execute_instruction().unwrap();
+        assert_eq!(&vm.registers[1..5], &[10, 20, 30, 30]);
+    }
+
+    #[test]
+    fn test_rmov_out_of_range() {
+        let mut vm = VM::new();
+        vm.registers[0] = 4;
+        vm.program = vec![Opcode::RMOV as u8, 30, 1, 0];
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidRegisterRange { base: 30, count: 4 })
+        );
+    }
+
+    #[test]
+    fn test_custom_syscall() {
+        fn square(vm: &mut VM) {
+            let value = vm.register(0);
+            vm.set_register(0, value * value).unwrap();
+        }
+
+        let mut vm = VM::new();
+        vm.register_syscall(42, square);
+        vm.registers[0] = 7;
+        vm.program = vec![Opcode::SYS as u8, 0, 42, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.register(0), 49);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_capture_output() {
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.registers[0] = 42;
+        vm.program = vec![Opcode::SYS as u8, 0, SYSCALL_PRINT_INT, 0];
+        vm.execute_instruction().unwrap();
+        vm.write_output("done");
+        assert_eq!(vm.take_output(), "42\ndone");
+        assert_eq!(vm.take_output(), "");
+    }
+
+    #[test]
+    fn test_unknown_syscall() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::SYS as u8, 0, 42, 0];
+        assert_eq!(vm.execute_instruction(), Err(VMError::UnknownSyscall(42)));
+
+        // The number isn't truncated to the syscall it wraps around to.
+        let mut vm = VM::new();
+        vm.capture_output();
+        vm.program = vec![Opcode::SYS as u8, 1, SYSCALL_PRINT_INT, 0];
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::UnknownSyscall(256 + u16::from(SYSCALL_PRINT_INT)))
+        );
+        assert_eq!(vm.take_output(), "");
+    }
+
+    #[test]
+    fn test_amoadd() {
+        let shared: SharedHeap = Arc::new(Mutex::new(vec![0; 8]));
+
+        let handles: Vec<_> = [3, 4]
+            .iter()
+            .map(|&value| {
+                let mut vm = get_vm().with_shared_heap(Arc::clone(&shared));
+                vm.registers[1] = 4;
+                vm.registers[2] = value;
+                for _ in 0..100 {
+                    // AMOADD $0 $1 $2
+                    vm.add_bytes(&[Opcode::AMOADD as u8, 0, 1, 2]);
+                }
+                std::thread::spawn(move || vm.run())
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Ok(HaltReason::EndOfProgram));
+        }
+
+        assert_eq!(&shared.lock().unwrap()[4..8], &700i32.to_be_bytes());
+
+        // A VM that panicked while holding the lock doesn't stop the others.
+        poison(&shared);
+        let mut vm = get_vm().with_shared_heap(Arc::clone(&shared));
+        vm.registers[1] = 4;
+        vm.registers[2] = 1;
+        vm.add_bytes(&[Opcode::AMOADD as u8, 0, 1, 2]);
+        assert_eq!(vm.run(), Ok(HaltReason::EndOfProgram));
+        assert_eq!(vm.register(0), 700);
+    }
+
+    // Poisons the lock of the shared heap by panicking while holding it.
+    fn poison(shared: &SharedHeap) {
+        let shared = Arc::clone(shared);
+        let result = std::thread::spawn(move || {
+            let _heap = shared.lock().unwrap();
+            panic!("poisoning the shared heap");
+        })
+        .join();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_input_buffer() {
+        let mut input = vec![];
+        for value in &[7, -1, 300] {
+            input.extend_from_slice(&i32::to_be_bytes(*value));
+        }
+
+        let inarg = Opcode::INARG as u8;
+        let hlt = Opcode::HLT as u8;
+        let mut vm = get_vm();
+        vm.set_input_buffer(input.clone()).unwrap();
+        vm.registers[1] = 2;
+        vm.registers[2] = 3;
+        vm.add_bytes(&[
+            inarg, 0, 1, 0, // INARG $0 $1
+            inarg, 3, 2, 0, // INARG $3 $2
+            hlt, 0, 0, 0,
+        ]);
+        assert_eq!(
+            vm.run(),
+            Err(VMError::InvalidHeapAddress(INPUT_BUFFER_BASE + 12))
+        );
+        assert_eq!(vm.register(0), 300);
+        assert_eq!(&vm.heap()[INPUT_BUFFER_BASE..], &input[..]);
+
+        // Elements are read from the heap so writes to the buffer are seen.
+        vm.heap_mut()[INPUT_BUFFER_BASE + 3] = 9;
+        vm.registers[1] = 0;
+        vm.pc = BIN_HEADER_LENGTH;
+        assert_eq!(vm.execute_instruction(), Ok(None));
+        assert_eq!(vm.register(0), 9);
+
+        vm.registers[1] = -1;
+        vm.pc = BIN_HEADER_LENGTH;
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidHeapAddress(INPUT_BUFFER_BASE - 4))
+        );
+    }
+
+    #[test]
+    fn test_input_buffer_heap_limit() {
+        let mut vm = VM::builder()
+            .max_heap(INPUT_BUFFER_BASE + 4)
+            .build()
+            .unwrap();
+        assert_eq!(
+            vm.set_input_buffer(vec![0; 8]),
+            Err(VMError::HeapLimitExceeded(INPUT_BUFFER_BASE + 8))
+        );
+        assert!(vm.heap().is_empty());
+        assert_eq!(vm.set_input_buffer(vec![0; 4]), Ok(()));
+        assert_eq!(vm.heap().len(), INPUT_BUFFER_BASE + 4);
+    }
+
+    #[test]
+    fn test_loadm_storem() {
+        let program = Assembler::new()
+            .assemble(
+                "load $0 #8\naloc $0\nload $1 #4\nloadw $2 #-70000\nstorem $1 $2\nloadm $3 $1\nhlt",
+            )
+            .unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(3), -70000);
+        assert_eq!(&vm.heap()[4..], &(-70000i32).to_be_bytes());
+
+        // The whole word has to fit in the heap.
+        vm.registers[1] = 5;
+        vm.program = vec![Opcode::LOADM as u8, 3, 1, 0xFF];
+        vm.pc = 0;
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidHeapAddress(5))
+        );
+        vm.program = vec![Opcode::STOREM as u8, 1, 2, 0xFF];
+        vm.pc = 0;
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidHeapAddress(5))
+        );
+    }
+
+    #[test]
+    fn test_lmw_smw() {
+        let mut vm = VM::new();
+        for value in &[7, -1, 300, 9] {
+            vm.heap.extend_from_slice(&i32::to_be_bytes(*value));
+        }
+        vm.registers[4] = 4;
+        vm.registers[5] = 3;
+        vm.program = vec![Opcode::LMW as u8, 0, 4, 5];
+        vm.execute_instruction().unwrap();
+        assert_eq!(&vm.registers[..3], &[-1, 300, 9]);
+
+        vm.registers[4] = 0;
+        vm.registers[5] = 2;
+        vm.program = vec![Opcode::SMW as u8, 1, 4, 5];
+        vm.pc = 0;
+        vm.execute_instruction().unwrap();
+        assert_eq!(&vm.heap[..8], &[0, 0, 1, 44, 0, 0, 0, 9]);
+
+        // Both the registers and the heap range are checked.
+        vm.registers[4] = 8;
+        vm.registers[5] = 3;
+        vm.program = vec![Opcode::LMW as u8, 0, 4, 5];
+        vm.pc = 0;
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidHeapAddress(8))
+        );
+
+        vm.registers[4] = 0;
+        vm.program = vec![Opcode::LMW as u8, 30, 4, 5];
+        vm.pc = 0;
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidRegisterRange { base: 30, count: 3 })
+        );
+    }
+
+    #[test]
+    fn test_xorrange() {
+        let mut vm = VM::new();
+        vm.heap = vec![0xFF, 0x12, 0x34, 0xF0, 0xFF];
+        vm.registers[1] = 1;
+        vm.registers[2] = 3;
+        vm.program = vec![Opcode::XORRANGE as u8, 0, 1, 2];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.registers[0], 0x12 ^ 0x34 ^ 0xF0);
+
+        vm.registers[2] = 5;
+        vm.pc = 0;
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidHeapAddress(1))
+        );
+    }
+
+    #[test]
+    fn test_bzero() {
+        let mut vm = VM::new();
+        vm.heap = vec![1, 2, 3, 4, 5, 6];
+        vm.registers[0] = 1;
+        vm.registers[1] = 4;
+        vm.program = vec![Opcode::BZERO as u8, 0, 1, 0xFF];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.heap, vec![1, 0, 0, 0, 0, 6]);
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_bzero_out_of_bounds() {
+        let mut vm = VM::new();
+        vm.heap = vec![1, 2, 3, 4];
+        vm.registers[0] = 2;
+        vm.registers[1] = 3;
+        vm.program = vec![Opcode::BZERO as u8, 0, 1, 0xFF];
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidHeapAddress(2))
+        );
+        assert_eq!(vm.heap, vec![1, 2, 3, 4]);
+
+        vm.registers[1] = -1;
+        vm.pc = 0;
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidHeapAddress(2))
+        );
+    }
+
+    #[test]
+    fn test_xchg() {
+        let shared: SharedHeap = Arc::new(Mutex::new(vec![0; 8]));
+        shared.lock().unwrap()[4..8].copy_from_slice(&9i32.to_be_bytes());
+
+        let mut vm = VM::new().with_shared_heap(Arc::clone(&shared));
+        vm.registers[0] = -5;
+        vm.registers[1] = 4;
+        vm.program = vec![Opcode::XCHG as u8, 0, 1, 0xFF];
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.registers[0], 9);
+        assert_eq!(&shared.lock().unwrap()[4..8], &(-5i32).to_be_bytes());
+        assert_eq!(vm.pc, 4);
+
+        vm.registers[1] = 5;
+        vm.pc = 0;
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidSharedAddress(5))
+        );
+
+        poison(&shared);
+        vm.registers[1] = 4;
+        vm.pc = 0;
+        vm.execute_instruction().unwrap();
+        assert_eq!(vm.registers[0], -5);
+    }
+
+    #[test]
+    fn test_amoadd_out_of_bounds() {
+        let mut vm = VM::new();
+        vm.registers[1] = 6;
+        vm.program = vec![Opcode::AMOADD as u8, 0, 1, 2];
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidSharedAddress(6))
+        );
+
+        let mut vm = VM::new().with_shared_heap(Arc::new(Mutex::new(vec![0; 8])));
+        vm.registers[1] = 6;
+        vm.program = vec![Opcode::AMOADD as u8, 0, 1, 2];
+        assert_eq!(
+            vm.execute_instruction(),
+            Err(VMError::InvalidSharedAddress(6))
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let mut vm = VM::new();
+        vm.registers[1] = 10;
+        vm.registers[2] = 20;
+        // SELECT $0 $1 $2
+        vm.program = vec![Opcode::SELECT as u8, 0, 1, 2];
+
+        vm.equal_flag = true;
+        vm.execute_instruction().unwrap();
+        assert_eq!(10, vm.register(0));
+        assert_eq!(4, vm.pc);
+
+        vm.equal_flag = false;
+        vm.pc = 0;
+        vm.execute_instruction().unwrap();
+        assert_eq!(20, vm.register(0));
+    }
+
+    #[test]
+    fn test_setz() {
+        let mut vm = VM::new();
+        vm.registers[0] = 7;
+        vm.registers[1] = 7;
+        let eq = Opcode::EQ as u8;
+        let setz = Opcode::SETZ as u8;
+        vm.program = vec![eq, 0, 1, 0, setz, 2, 0, 0, eq, 0, 3, 0, setz, 2, 0, 0];
+        vm.execute_instruction().unwrap();
+        vm.execute_instruction().unwrap();
+        assert_eq!(1, vm.register(2));
+
+        vm.execute_instruction().unwrap();
+        vm.execute_instruction().unwrap();
+        assert_eq!(0, vm.register(2));
+        assert_eq!(16, vm.pc);
+    }
+
+    #[test]
+    fn test_setnz() {
+        let mut vm = VM::new();
+        vm.registers[0] = 7;
+        vm.registers[1] = 7;
+        let eq = Opcode::EQ as u8;
+        let setnz = Opcode::SETNZ as u8;
+        vm.program = vec![eq, 0, 1, 0, setnz, 2, 0, 0, eq, 0, 3, 0, setnz, 2, 0, 0];
+        vm.execute_instruction().unwrap();
+        vm.execute_instruction().unwrap();
+        assert_eq!(0, vm.register(2));
+
+        vm.execute_instruction().unwrap();
+        vm.execute_instruction().unwrap();
+        assert_eq!(1, vm.register(2));
+    }
+
+    #[test]
+    fn test_sp() {
+        let program = Assembler::new()
+            .assemble("sp $3\nload $0 #10\npush $0\npush $0\nsp $4\nhlt")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.registers[3] = 99;
+        vm.add_bytes(&program);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(0, vm.register(3));
+        assert_eq!(2, vm.register(4));
+    }
+
+    #[test]
+    fn test_lprog() {
+        let mut vm = VM::new();
+        vm.registers[1] = 5;
+        vm.program = vec![Opcode::LPROG as u8, 0, 1, 0, 7, 42];
+        vm.execute_instruction().unwrap();
+        assert_eq!(42, vm.register(0));
+        assert_eq!(4, vm.pc);
+
+        vm.registers[1] = 6;
+        vm.pc = 0;
+        assert_eq!(
+            Err(VMError::InvalidProgramAddress(6)),
+            vm.execute_instruction()
+        );
+    }
+
+    #[test]
+    fn test_sprog() {
+        let mut vm = get_vm();
+        vm.registers[1] = 99;
+        vm.registers[2] = (BIN_HEADER_LENGTH + 7) as i32;
+        // SPROG $1 $2
+        // LOAD $0 #10 which gets patched to LOAD $0 #99
+        let sprog = Opcode::SPROG as u8;
+        let load = Opcode::LOAD as u8;
+        vm.add_bytes(&[sprog, 1, 2, 0, load, 0, 0, 10]);
+        vm.run().unwrap();
+        assert_eq!(99, vm.register(0));
+
+        vm.registers[2] = 1000;
+        vm.pc = BIN_HEADER_LENGTH;
+        assert_eq!(
+            Err(VMError::InvalidProgramAddress(1000)),
+            vm.execute_instruction()
+        );
+    }
+
+    #[test]
+    fn test_call_ret() {
+        let mut vm = VM::new();
+        vm.registers[0] = 8;
+        let call = Opcode::CALL as u8;
+        let ret = Opcode::RET as u8;
+        vm.program = vec![call, 0, 0, 0, 0, 0, 0, 0, ret, 0, 0, 0];
+        vm.execute_instruction().unwrap();
+        assert_eq!(8, vm.pc);
+        assert_eq!(vec![4], vm.call_stack);
+
+        vm.execute_instruction().unwrap();
+        assert_eq!(4, vm.pc);
+        assert_eq!(0, vm.call_depth());
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        let mut vm = get_vm().with_max_call_depth(8);
+        // CALL $0 where $0 points back at the CALL itself.
+        vm.registers[0] = BIN_HEADER_LENGTH as i32;
+        vm.add_bytes(&[Opcode::CALL as u8, 0, 0, 0]);
+        assert_eq!(Err(VMError::StackOverflow), vm.run());
+        assert_eq!(8, vm.call_depth());
+    }
+
+    #[test]
+    fn test_ret_with_empty_call_stack() {
+        let mut vm = get_vm();
+        vm.add_bytes(&[Opcode::RET as u8, 0, 0, 0]);
+        assert_eq!(Err(VMError::StackUnderflow), vm.run());
+    }
+
+    #[test]
+    fn test_load_program() {
+        let program = Assembler::new()
+            .assemble("load $0 #5\nhlt\n.data\n.word #9")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.load_program(&program).unwrap();
+        assert_eq!(vm.pc(), BIN_HEADER_LENGTH);
+        assert_eq!(vm.heap(), &[0, 9]);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(0), 5);
+
+        let mut bad_magic = program.clone();
+        bad_magic[0] = 0;
+        assert_eq!(vm.load_program(&bad_magic), Err(VMError::InvalidHeader));
+        assert_eq!(vm.load_program(&program[..8]), Err(VMError::InvalidHeader));
+
+        let mut bad_version = program;
+        bad_version[4] = 7;
+        assert_eq!(
+            vm.load_program(&bad_version),
+            Err(VMError::UnsupportedVersion(7))
+        );
+    }
+
+    #[test]
+    fn test_data_isnt_executed() {
+        // The data section holds the bytes of LOAD $0 #9.
+        let data = i32::from_be_bytes([Opcode::LOAD as u8, 0, 0, 9]);
+        let program = Assembler::new()
+            .assemble(&format!("load $0 #5\n.data\n.long #{}", data))
+            .unwrap();
+        let mut vm = VM::new();
+        vm.load_program(&program).unwrap();
+        assert_eq!(vm.run(), Ok(HaltReason::EndOfProgram));
+        assert_eq!(vm.register(0), 5);
+        assert_eq!(vm.cycles(), 1);
+    }
+
+    #[test]
+    fn test_append_code() {
+        let mut vm = VM::new();
+        vm.append_code(&[Opcode::LOAD as u8, 0, 0, 5]).unwrap();
+        assert_eq!(vm.run_once(), Ok(None));
+        assert_eq!(vm.program().len(), BIN_HEADER_LENGTH + 4);
+
+        // The data section stays after the code.
+        let program = Assembler::new()
+            .assemble("load $0 @value\n.data\nvalue: .word #9")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.load_program(&program).unwrap();
+        vm.append_code(&[Opcode::HLT as u8, 0, 0, 0]).unwrap();
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(parse_header(vm.program()).unwrap().data_start, 72);
+        assert_eq!(&vm.program()[72..], &[0, 9]);
+    }
+
+    #[test]
+    fn test_run_outcome() {
+        let mut vm = get_vm();
+        vm.add_bytes(&[Opcode::HLT as u8, 0, 0, 0]);
+        assert_eq!(Ok(HaltReason::Halted), vm.run());
+
+        let mut vm = get_vm();
+        assert_eq!(Ok(HaltReason::EndOfProgram), vm.run());
+
+        let mut vm = get_vm();
+        vm.add_bytes(&[200, 0, 0, 0]);
+        assert_eq!(Err(VMError::IllegalOpcode(200)), vm.run());
+
+        let mut vm = VM::new();
+        vm.add_bytes(&[1, 2]);
+        assert_eq!(Err(VMError::InvalidHeader), vm.run());
+    }
+
+    #[test]
+    fn test_run_until_breakpoint() {
+        let program = Assembler::new()
+            .assemble("load $0 #1\nload $1 #2\nadd $0 $1 $2\nload $3 #4\nhlt")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        let add = BIN_HEADER_LENGTH + 8;
+        vm.add_breakpoint(add);
+        vm.add_breakpoint(add);
+        assert_eq!(vm.breakpoints(), &[add]);
+
+        // Pauses before the ADD is executed.
+        assert_eq!(vm.run_until_breakpoint(None), Ok(None));
+        assert_eq!(vm.pc(), add);
+        assert_eq!((vm.register(0), vm.register(1)), (1, 2));
+        assert_eq!(vm.register(2), 0);
+
+        // Resuming executes the instruction at the breakpoint.
+        assert_eq!(vm.run_until_breakpoint(None), Ok(Some(HaltReason::Halted)));
+        assert_eq!((vm.register(2), vm.register(3)), (3, 4));
+    }
+
+    #[test]
+    fn test_cycles() {
+        let program = Assembler::new()
+            .assemble("load $0 #1\nload $1 #2\nadd $0 $1 $2\nhlt")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.cycles(), 0);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.cycles(), 4);
+
+        // Running off the end doesn't execute anything.
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::LOAD as u8, 0, 0, 1];
+        assert_eq!(vm.execute_instruction(), Ok(None));
+        assert_eq!(vm.execute_instruction(), Ok(Some(HaltReason::EndOfProgram)));
+        assert_eq!(vm.cycles(), 1);
+    }
+
+    #[test]
+    fn test_run_with_budget() {
+        let program = Assembler::new()
+            .assemble("load $1 #1\nloop: add $0 $1 $0\njmp @loop")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(
+            vm.run_with_budget(Some(101)),
+            Err(VMError::BudgetExceeded(101))
+        );
+        assert_eq!(vm.cycles(), 101);
+        assert_eq!(vm.register(0), 50);
+
+        // The budget applies to each call.
+        assert_eq!(
+            vm.run_until_breakpoint(Some(10)),
+            Err(VMError::BudgetExceeded(10))
+        );
+        assert_eq!(vm.cycles(), 111);
+
+        let mut vm = VM::new();
+        vm.add_bytes(&Assembler::new().assemble("load $0 #1\nhlt").unwrap());
+        assert_eq!(vm.run_with_budget(Some(2)), Ok(HaltReason::Halted));
+    }
+
+    #[test]
+    fn test_run_once_outcome() {
+        let mut vm = get_vm();
+        vm.add_bytes(&[Opcode::LOAD as u8, 0, 0, 1, Opcode::HLT as u8]);
+        assert_eq!(Ok(None), vm.run_once());
+        assert_eq!(1, vm.register(0));
+        assert_eq!(Ok(Some(HaltReason::Halted)), vm.run_once());
+
+        let mut vm = get_vm();
+        vm.add_bytes(&[Opcode::LOAD as u8, 0, 0, 1]);
+        assert_eq!(Ok(None), vm.run_once());
+        assert_eq!(Ok(Some(HaltReason::EndOfProgram)), vm.run_once());
+
+        let mut vm = get_vm();
+        vm.add_bytes(&[200, 0, 0, 0]);
+        assert_eq!(Err(VMError::IllegalOpcode(200)), vm.run_once());
+
+        // Like run, stepping needs a program with a header.
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::LOAD as u8, 0, 0, 1];
+        assert_eq!(Err(VMError::InvalidHeader), vm.run_once());
+    }
+
+    #[test]
+    fn test_run_steps_reporting() {
+        let load = Opcode::LOAD as u8;
+        let jmp = Opcode::JMP as u8;
+        let mut vm = get_vm();
+        vm.add_bytes(&[
+            load, 0, 0, 68, // LOAD $0 #68
+            jmp, 0, 0, 0, // 68: JMP $0
+        ]);
+        let mut reports = vec![];
+        let result =
+            vm.run_steps_reporting(2 * PROGRESS_INTERVAL + 500, |steps| reports.push(steps));
+        assert_eq!(result, Ok(None));
+        assert_eq!(reports, vec![PROGRESS_INTERVAL, 2 * PROGRESS_INTERVAL]);
+
+        // Programs that halt within the budget report their halt reason.
+        let mut vm = get_vm();
+        vm.add_bytes(&[Opcode::LOAD as u8, 0, 0, 1, Opcode::HLT as u8, 0, 0, 0]);
+        let mut reports = 0;
+        let result = vm.run_steps_reporting(PROGRESS_INTERVAL, |_| reports += 1);
+        assert_eq!(result, Ok(Some(HaltReason::Halted)));
+        assert_eq!(reports, 0);
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let load = Opcode::LOAD as u8;
+        let push = Opcode::PUSH as u8;
+        let pop = Opcode::POP as u8;
+        let hlt = Opcode::HLT as u8;
+        let mut vm = get_vm();
+        vm.add_bytes(&[
+            load, 0, 0, 1, // LOAD $0 #1
+            load, 1, 0, 2, // LOAD $1 #2
+            push, 0, 0, 0, // PUSH $0
+            push, 1, 0, 0, // PUSH $1
+            pop, 2, 0, 0, // POP $2
+            pop, 3, 0, 0, // POP $3
+            hlt, 0, 0, 0,
+        ]);
+        assert_eq!(vm.run(), Ok(HaltReason::Halted));
+        assert_eq!(vm.register(2), 2);
+        assert_eq!(vm.register(3), 1);
+
+        let mut vm = VM::new();
+        vm.program = vec![pop, 0, 0, 0];
+        assert_eq!(vm.execute_instruction(), Err(VMError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_step_over() {
+        let mut vm = get_vm();
+        vm.registers[0] = 72;
+        // CALL $0
+        // HLT
+        // LOAD $1 #99
+        // RET
+        let call = Opcode::CALL as u8;
+        let hlt = Opcode::HLT as u8;
+        let load = Opcode::LOAD as u8;
+        let ret = Opcode::RET as u8;
+        vm.add_bytes(&[call, 0, 0, 0, hlt, 0, 0, 0, load, 1, 0, 99, ret, 0, 0, 0]);
+        vm.step_over(None).unwrap();
+        assert_eq!(68, vm.pc);
+        assert_eq!(99, vm.register(1));
+        assert_eq!(0, vm.call_depth());
+
+        // A subroutine that never returns runs out of budget.
+        let program = Assembler::new()
+            .assemble("call @forever\nhlt\nforever: jmp @forever")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(&program);
+        assert_eq!(vm.step_over(Some(100)), Err(VMError::BudgetExceeded(100)));
+        assert_eq!(vm.cycles(), 100);
+    }
+
+    #[test]
+    fn test_step_over_non_call() {
+        let mut vm = get_vm();
+        vm.add_bytes(&[Opcode::LOAD as u8, 0, 1, 244]);
+        vm.step_over(None).unwrap();
+        assert_eq!(68, vm.pc);
+        assert_eq!(500, vm.register(0));
+    }
+
+    #[test]
+    fn test_registers_iterator() {
+        let mut vm = VM::new();
+        for i in 0..MAX_REGISTERS {
+            vm.registers[i] = i as i32;
+        }
+
+        for (i, r) in vm.registers().enumerate() {
+            assert_eq!(i as i32, r);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let mut vm = VM::new();
+        vm.registers[0] = 10;
+        vm.registers[1] = 20;
+        vm.program = vec![Opcode::ADD as u8, 0, 1, 2];
+
+        let before = vm.snapshot();
+        vm.execute_instruction().unwrap();
+        let changes = before.diff(&vm.snapshot());
+        assert_eq!(
+            changes,
+            vec![StateChange::Register {
+                index: 2,
+                old: 0,
+                new: 30
+            }]
+        );
+        assert_eq!(changes[0].to_string(), "$2: 0 -> 30");
+
+        vm.equal_flag = true;
+        vm.remainder = 3;
+        assert_eq!(
+            before.diff(&vm.snapshot())[1..],
+            [
+                StateChange::EqualFlag {
+                    old: false,
+                    new: true
+                },
+                StateChange::Remainder { old: 0, new: 3 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_byte() {
+        let mut vm = VM::new();
+        vm.add_byte(1);
+        assert_eq!(vm.program[0], 1);
+    }
+
+    #[test]
+    fn test_add_bytes() {
+        let mut vm = VM::new();
+        vm.add_bytes(&[1, 2]);
+        assert_eq!(vm.program, &[1, 2]);
+    }
+}